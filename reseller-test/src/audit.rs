@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{Address, B256};
+use kinode_process_lib::{
+    kiprintln,
+    kimap
+};
+
+use crate::structs::{
+    DataKey,
+    ResellerState
+};
+
+/// Names checked when the request doesn't set a sample size.
+const DEFAULT_AUDIT_SAMPLE: usize = 100;
+/// Upper bound on names checked in one audit, since each costs at least one `eth_call`.
+const MAX_AUDIT_SAMPLE: usize = 1_000;
+/// Children read per page while walking the subtree.
+const AUDIT_PAGE_SIZE: usize = 500;
+
+/// Compare part of the index against the kimap contract.
+///
+/// # Example
+/// ```
+/// let request = AuditIndex {
+///     root: "test-api-registry.os".to_string(),
+///     sample: Some(50),
+///     repair: true,
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct AuditIndex {
+    /// full name of the subtree root; the root itself is checked too unless it is the kimap root
+    pub root: String,
+    /// maximum number of names to check, walking the subtree breadth-first
+    pub sample: Option<usize>,
+    /// refetch the logs of every mismatched name and replay them into the index
+    pub repair: bool,
+}
+
+/// One disagreement between the index and the chain.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct AuditMismatch {
+    pub name: String,
+    pub namehash: String,
+    /// "entry", "owner", "tba" or "data:<label>"
+    pub field: String,
+    pub local: Option<String>,
+    pub chain: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct AuditReport {
+    pub checked: usize,
+    pub mismatches: Vec<AuditMismatch>,
+    /// namehashes whose logs were replayed
+    pub repaired: Vec<String>,
+    /// names that could not be checked or repaired, with the reason
+    pub errors: Vec<String>,
+}
+
+/// Walk the subtree below `request.root`, check each name against `kimap.get`, and
+/// optionally repair mismatched names by replaying their logs.
+pub fn audit_index(state: &mut ResellerState, request: AuditIndex) -> anyhow::Result<AuditReport> {
+    let sample = request.sample.unwrap_or(DEFAULT_AUDIT_SAMPLE).min(MAX_AUDIT_SAMPLE);
+    let root_hash = state
+        .db
+        .namehash_of(&request.root)?
+        .ok_or_else(|| anyhow::anyhow!("Name not found: {}", request.root))?;
+
+    let mut report = AuditReport {
+        checked: 0,
+        mismatches: vec![],
+        repaired: vec![],
+        errors: vec![],
+    };
+
+    for (name, namehash) in sample_subtree(state, &request.root, &root_hash, sample)? {
+        match audit_name(state, &name, &namehash) {
+            Ok(mismatches) => report.mismatches.extend(mismatches),
+            Err(e) => report.errors.push(format!("{name}: {e}")),
+        }
+        report.checked += 1;
+    }
+
+    if request.repair {
+        let mut affected: Vec<String> = report
+            .mismatches
+            .iter()
+            .map(|mismatch| mismatch.namehash.clone())
+            .collect();
+        affected.dedup();
+        // replaying logs can't fix an entry the chain doesn't have
+        let unminted: Vec<String> = report
+            .mismatches
+            .iter()
+            .filter(|mismatch| mismatch.field == "entry")
+            .map(|mismatch| mismatch.namehash.clone())
+            .collect();
+        for namehash in affected {
+            if unminted.contains(&namehash) {
+                report.errors.push(format!("repair of {namehash}: not minted on chain, skipped"));
+                continue;
+            }
+            match repair_name(state, &namehash) {
+                Ok(()) => report.repaired.push(namehash),
+                Err(e) => report.errors.push(format!("repair of {namehash}: {e}")),
+            }
+        }
+    }
+
+    kiprintln!(
+        "audited {} names under {:?}: {} mismatches, {} repaired",
+        report.checked,
+        request.root,
+        report.mismatches.len(),
+        report.repaired.len()
+    );
+    Ok(report)
+}
+
+/// Up to `sample` `(full name, namehash)` pairs from the subtree, breadth-first.
+/// The kimap root has no entry on chain, so it is never part of the sample.
+fn sample_subtree(
+    state: &ResellerState,
+    root_name: &str,
+    root_hash: &str,
+    sample: usize,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut names = vec![];
+    let mut queue = VecDeque::from([(root_name.to_string(), root_hash.to_string())]);
+
+    while let Some((name, namehash)) = queue.pop_front() {
+        if names.len() >= sample {
+            break;
+        }
+        if namehash != kimap::KIMAP_ROOT_HASH {
+            names.push((name, namehash.clone()));
+        }
+        let mut offset = 0;
+        loop {
+            let page = state.db.children(&namehash, offset, AUDIT_PAGE_SIZE)?;
+            let page_len = page.len();
+            queue.extend(page.into_iter().map(|child| (child.name, child.namehash)));
+            if page_len < AUDIT_PAGE_SIZE || queue.len() >= sample {
+                break;
+            }
+            offset += page_len;
+        }
+    }
+    Ok(names)
+}
+
+/// Compare one name's owner, tba and data keys with the chain.
+fn audit_name(state: &mut ResellerState, name: &str, namehash: &str) -> anyhow::Result<Vec<AuditMismatch>> {
+    let (tba, owner, _data) = state
+        .kimap
+        .get_hash(namehash)
+        .map_err(|e| anyhow::anyhow!("kimap lookup failed: {e:?}"))?;
+
+    let mismatch = |field: &str, local: Option<String>, chain: Option<String>| AuditMismatch {
+        name: name.to_string(),
+        namehash: namehash.to_string(),
+        field: field.to_string(),
+        local,
+        chain,
+    };
+
+    // an entry that was never minted has no tba
+    if tba == Address::ZERO {
+        return Ok(vec![mismatch("entry", Some(name.to_string()), None)]);
+    }
+
+    let mut mismatches = vec![];
+    let ownership = state.db.get_ownership(namehash)?.unwrap_or_default();
+    if ownership.owner != Some(owner) {
+        mismatches.push(mismatch(
+            "owner",
+            ownership.owner.map(|owner| owner.to_string()),
+            Some(owner.to_string()),
+        ));
+    }
+    if ownership.tba.is_some_and(|local| local != tba) {
+        mismatches.push(mismatch(
+            "tba",
+            ownership.tba.map(|tba| tba.to_string()),
+            Some(tba.to_string()),
+        ));
+    }
+
+    let Some(node) = state.db.get_node(namehash)? else {
+        return Ok(mismatches);
+    };
+    for (label, data_key) in &node.data_keys {
+        let local = match data_key {
+            DataKey::Fact(fact) => &fact.data,
+            // note will never have an empty vector
+            DataKey::Note(notes) => &notes.last().unwrap().data,
+        };
+        let data_hash = kimap::namehash(&format!("{label}.{name}"));
+        let (_, _, chain) = state
+            .kimap
+            .get_hash(&data_hash)
+            .map_err(|e| anyhow::anyhow!("kimap lookup of {label} failed: {e:?}"))?;
+        if chain.as_ref() != Some(local) {
+            mismatches.push(mismatch(
+                &format!("data:{label}"),
+                Some(local.to_string()),
+                chain.map(|data| data.to_string()),
+            ));
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Refetch every kimap log about one namehash up to the sync cursor and replay them in order.
+///
+/// The entry's own `Mint` has it as `childhash` (topic 2), its notes, facts, gene and
+/// child mints have it as topic 1, and its token `Transfer`s and tba's `ERC6551AccountCreated`
+/// carry it as the token id (topic 3).
+/// Each is fetched in the same adaptive chunks as the history sync.
+fn repair_name(state: &mut ResellerState, namehash: &str) -> anyhow::Result<()> {
+    let hash = B256::from_str(namehash)?;
+    let base = ResellerState::make_filter(&state.kimap);
+    let (from, to) = (state.sync.first_block, state.sync.last_block);
+
+    let filters = [
+        base.clone().topic1(hash),
+        base.clone().topic2(hash),
+        base.topic3(hash),
+        ResellerState::make_account_filter(&state.kimap).topic3(hash),
+    ];
+    let mut logs = vec![];
+    state.get_logs_chunked(&filters, from, to, |_, chunk, _| {
+        logs.extend(chunk);
+        Ok(())
+    })?;
+    logs.sort_by_key(|log| (log.block_number, log.log_index));
+    logs.dedup_by_key(|log| (log.block_number, log.log_index));
+
+    for log in &logs {
+        state.apply_log(log)?;
+    }
+    state.save();
+    Ok(())
+}
@@ -0,0 +1,257 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::Value;
+use process_macros::SerdeJsonInto;
+use kinode_process_lib::kiprintln;
+
+use crate::decoders::has_fixed_encoding;
+use crate::structs::{
+    DataVersion,
+    ResellerState
+};
+
+/// List the valid API specs published by resellers, optionally filtered.
+///
+/// # Example
+/// ```
+/// let request = ListAllApis {
+///     provider: Some("anthropic".to_string()),
+///     model: None,
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ListAllApis {
+    /// case-insensitive match on the spec's provider
+    pub provider: Option<String>,
+    /// exact match on one of the spec's models
+    pub model: Option<String>,
+}
+
+/// Fetch one reseller's API spec.
+///
+/// # Example
+/// ```
+/// let request = GetApiSpec {
+///     reseller: "zapi-registry1111.test-api-registry.os".to_string(),
+///     api: "~llm-proxy".to_string(),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct GetApiSpec {
+    /// full name of the reseller entry
+    pub reseller: String,
+    /// label of the note holding the spec
+    pub api: String,
+}
+
+/// The catalog fields of a spec. Each is read from the top level of the document,
+/// or from its `info` object as in OpenAPI.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone, Default)]
+pub struct ApiSummary {
+    pub title: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// `x-api-type`, e.g. "REST"
+    pub api_type: Option<String>,
+    /// `x-protocol`, e.g. "HTTP"
+    pub protocol: Option<String>,
+    /// `provider` or `x-provider`, e.g. "anthropic"
+    pub provider: Option<String>,
+    /// `models` or `x-models`, as names
+    pub models: Vec<String>,
+    /// `pricing` or `x-pricing`, as published
+    pub pricing: Option<Value>,
+}
+
+/// One spec in the catalog.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ApiCatalogEntry {
+    /// full name of the reseller entry the spec is written on
+    pub reseller: String,
+    pub namehash: String,
+    /// label of the note holding the spec
+    pub api: String,
+    /// block of the spec's latest version
+    pub block: Option<u64>,
+    #[serde(flatten)]
+    pub summary: ApiSummary,
+    /// why the spec is left out of `ListAllApis`; empty if it is valid
+    pub errors: Vec<String>,
+}
+
+/// A catalog entry together with the full parsed document.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ApiSpec {
+    #[serde(flatten)]
+    pub entry: ApiCatalogEntry,
+    pub spec: Value,
+}
+
+/// Parse a spec written as JSON or YAML. Only mappings count as specs.
+pub fn parse_spec(data: &[u8]) -> Option<Value> {
+    let spec: Value = serde_json::from_slice(data)
+        .ok()
+        .or_else(|| serde_yaml::from_slice(data).ok())?;
+    spec.is_object().then_some(spec)
+}
+
+impl ApiSummary {
+    /// Read the catalog fields out of a spec, returning the summary and any validation errors.
+    pub fn from_spec(spec: &Value) -> (Self, Vec<String>) {
+        let mut errors = vec![];
+        let mut text = |keys: &[&str], required: bool| -> Option<String> {
+            match keys.iter().find_map(|key| field(spec, key)) {
+                Some(Value::String(text)) => Some(text.clone()),
+                Some(Value::Number(number)) => Some(number.to_string()),
+                Some(_) => {
+                    errors.push(format!("{} must be a string", keys[0]));
+                    None
+                }
+                None if required => {
+                    errors.push(format!("missing {}", keys[0]));
+                    None
+                }
+                None => None,
+            }
+        };
+
+        let title = text(&["title"], true);
+        let version = text(&["version"], false);
+        let description = text(&["description"], false);
+        let api_type = text(&["x-api-type"], true);
+        let protocol = text(&["x-protocol"], false);
+        let provider = text(&["provider", "x-provider"], false);
+
+        let models = match field(spec, "models").or_else(|| field(spec, "x-models")) {
+            None => vec![],
+            Some(Value::Array(models)) => models
+                .iter()
+                .filter_map(|model| match model {
+                    Value::String(name) => Some(name.clone()),
+                    // `{ "name": ... }` or `{ "id": ... }` objects describing each model
+                    Value::Object(model) => model
+                        .get("name")
+                        .or_else(|| model.get("id"))
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                    _ => None,
+                })
+                .collect(),
+            Some(_) => {
+                errors.push("models must be a list".to_string());
+                vec![]
+            }
+        };
+
+        let pricing = field(spec, "pricing").or_else(|| field(spec, "x-pricing")).cloned();
+        if pricing.as_ref().is_some_and(|pricing| !pricing.is_object()) {
+            errors.push("pricing must be an object".to_string());
+        }
+
+        let summary = ApiSummary {
+            title,
+            version,
+            description,
+            api_type,
+            protocol,
+            provider,
+            models,
+            pricing,
+        };
+        (summary, errors)
+    }
+}
+
+fn field<'a>(spec: &'a Value, key: &str) -> Option<&'a Value> {
+    spec.get(key)
+        .or_else(|| spec.get("info").and_then(|info| info.get(key)))
+}
+
+impl ApiCatalogEntry {
+    pub fn new(reseller: String, namehash: String, api: String, block: Option<u64>, spec: &Value) -> Self {
+        let (summary, errors) = ApiSummary::from_spec(spec);
+        ApiCatalogEntry {
+            reseller,
+            namehash,
+            api,
+            block,
+            summary,
+            errors,
+        }
+    }
+
+    fn matches(&self, request: &ListAllApis) -> bool {
+        let provider_matches = match &request.provider {
+            Some(provider) => self
+                .summary
+                .provider
+                .as_ref()
+                .is_some_and(|own| own.eq_ignore_ascii_case(provider)),
+            None => true,
+        };
+        let model_matches = match &request.model {
+            Some(model) => self.summary.models.contains(model),
+            None => true,
+        };
+        provider_matches && model_matches
+    }
+}
+
+/// Add or update a catalog entry if a `~` note holds an API spec, and drop the entry
+/// if the note was overwritten with something that isn't one.
+///
+/// Labels with a fixed kimap meaning, like `~ip` or `~routers`, are never specs.
+pub fn index_api_spec(state: &ResellerState, namehash: &str, label: &str, version: &DataVersion) -> anyhow::Result<()> {
+    if !label.starts_with('~') || has_fixed_encoding(label) {
+        return Ok(());
+    }
+    let Some(spec) = parse_spec(&version.data) else {
+        return state.db.delete_api_spec(namehash, label, version.block);
+    };
+    let Some(reseller) = state.db.node_row(namehash)? else {
+        return Ok(());
+    };
+    state
+        .db
+        .upsert_api_spec(namehash, label, &reseller.full_name, version.block, &spec)
+}
+
+/// Build the catalog from notes already in the index. Specs are otherwise only read as
+/// their logs are applied, so an index synced (or imported from a snapshot) before the
+/// catalog existed would stay without one.
+pub fn reindex_api_specs(state: &ResellerState) -> anyhow::Result<()> {
+    if state.db.has_api_specs()? {
+        return Ok(());
+    }
+    let notes = state.db.latest_notes("~")?;
+    for (namehash, label, version) in &notes {
+        index_api_spec(state, namehash, label, version)?;
+    }
+    kiprintln!("api catalog rebuilt from {} indexed notes", notes.len());
+    Ok(())
+}
+
+/// Every valid spec matching the request.
+pub fn list_all_apis(state: &ResellerState, request: ListAllApis) -> anyhow::Result<Vec<ApiCatalogEntry>> {
+    Ok(state
+        .db
+        .api_specs()?
+        .into_iter()
+        .map(|spec| spec.entry)
+        .filter(|entry| entry.errors.is_empty() && entry.matches(&request))
+        .collect())
+}
+
+/// One spec by reseller name and note label, valid or not.
+pub fn get_api_spec(state: &ResellerState, request: GetApiSpec) -> anyhow::Result<ApiSpec> {
+    let namehash = state
+        .db
+        .namehash_of(&request.reseller)?
+        .ok_or_else(|| anyhow::anyhow!("Reseller not found: {}", request.reseller))?;
+    state
+        .db
+        .api_spec(&namehash, &request.api)?
+        .ok_or_else(|| anyhow::anyhow!("{} has no API spec {}", request.reseller, request.api))
+}
@@ -0,0 +1,122 @@
+use std::str::FromStr;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::Address;
+use kinode_process_lib::{
+    our,
+    kiprintln,
+    eth,
+    kimap,
+    vfs
+};
+
+/// Package config file, relative to the package's VFS drive.
+const CONFIG_PATH: &str = "pkg/config.json";
+
+/// Chain and contract settings for the process, read from `pkg/config.json`.
+///
+/// The same build can index Optimism, a testnet or a local anvil devnet by shipping
+/// a different config. Missing fields fall back to the Optimism deployment.
+///
+/// # Example
+/// ```
+/// {
+///     "chain_id": 31337,
+///     "kimap_address": "0x5FbDB2315678afecb367f032d93F642f64180aa3",
+///     "kimap_first_block": 0,
+///     "rpc_timeout": 10,
+///     "api_registry_tba": "0x...",
+///     "reseller_impl": "0x...",
+///     "payment_token": "0x...",
+///     "payment_confirmations": 1,
+///     "require_payment": true,
+///     "usage_period": 86400,
+///     "usd_rate": "1"
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone, PartialEq)]
+#[serde(default)]
+pub struct PackageConfig {
+    pub chain_id: u64,
+    pub kimap_address: Address,
+    /// block the kimap contract was deployed in; history sync starts here
+    pub kimap_first_block: u64,
+    /// timeout for requests to `eth:distro:sys`, in seconds
+    pub rpc_timeout: u64,
+    /// tba of the ApiRegistry entry that resellers register under
+    pub api_registry_tba: Option<Address>,
+    /// implementation (gene) of reseller tbas
+    pub reseller_impl: Option<Address>,
+    /// ERC20 that prices and payments are in. The registry's `token()` is its ERC-6551
+    /// binding, not a currency, so the payment token has to be configured.
+    pub payment_token: Option<Address>,
+    /// blocks a payment transaction must be buried under before it is accepted
+    pub payment_confirmations: u64,
+    /// refuse calls from other nodes that don't attach a payment or voucher
+    pub require_payment: bool,
+    /// length of a usage root period in seconds; 0 turns usage roots off
+    pub usage_period: u64,
+    /// USD value of one payment token, as a decimal string; invoices leave out USD
+    /// amounts without it. Unset by default: even a stablecoin shouldn't be assumed at par.
+    pub usd_rate: Option<String>,
+}
+
+impl Default for PackageConfig {
+    fn default() -> Self {
+        Self {
+            chain_id: kimap::KIMAP_CHAIN_ID,
+            kimap_address: Address::from_str(kimap::KIMAP_ADDRESS).unwrap(),
+            kimap_first_block: kimap::KIMAP_FIRST_BLOCK,
+            rpc_timeout: 60,
+            api_registry_tba: Address::from_str("0x300174aBA9fB8912A8667eF2a818D959933428b0").ok(),
+            reseller_impl: Address::from_str("0xf410864FF8c7191707d29384cD02A838f2a42123").ok(),
+            // USDC on Optimism
+            payment_token: Address::from_str("0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85").ok(),
+            payment_confirmations: 2,
+            require_payment: true,
+            usage_period: 86400,
+            usd_rate: None,
+        }
+    }
+}
+
+impl PackageConfig {
+    /// Read the package config from VFS, falling back to the defaults if it is
+    /// missing or malformed.
+    pub fn load() -> Self {
+        let path = format!("/{}/{}", our().package_id(), CONFIG_PATH);
+        let bytes = match vfs::open_file(&path, false, None).and_then(|file| file.read()) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                kiprintln!("no package config at {path} ({e:?}), using Optimism defaults");
+                return Self::default();
+            }
+        };
+        match serde_json::from_slice(&bytes) {
+            Ok(config) => {
+                kiprintln!("loaded package config from {path}: {config:?}");
+                config
+            }
+            Err(e) => {
+                kiprintln!("malformed package config at {path} ({e}), using Optimism defaults");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn kimap(&self) -> kimap::Kimap {
+        kimap::Kimap::new(
+            eth::Provider::new(self.chain_id, self.rpc_timeout),
+            self.kimap_address,
+        )
+    }
+
+    /// Name of the sqlite database for this deployment, so switching chains or
+    /// contracts never mixes two indexes.
+    pub fn db_name(&self) -> String {
+        format!("kimap_index_{}_{:x}", self.chain_id, self.kimap_address)
+    }
+}
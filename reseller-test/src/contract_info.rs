@@ -0,0 +1,129 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{Address, U256};
+
+use crate::contracts::{
+    eth_call,
+    ApiRegistry,
+    Reseller
+};
+use crate::structs::ResellerState;
+
+/// Read a reseller's contract state directly from the chain.
+///
+/// # Example
+/// ```
+/// let request = GetResellerContractInfo {
+///     name: "zapi-registry1111.test-api-registry.os".to_string(),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct GetResellerContractInfo {
+    /// full kimap name of the reseller entry
+    pub name: String,
+}
+
+/// The ERC-6551 binding a tba reports from `token()`: the kimap token that owns it.
+/// This is not a payment token.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct TokenBinding {
+    pub chain_id: U256,
+    pub token_contract: Address,
+    pub token_id: U256,
+}
+
+/// What the ApiRegistry has stored under the entry's label.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct RegistryRegistration {
+    pub registry: Address,
+    pub label: String,
+    /// zero if the label was never registered
+    pub tba: Address,
+    pub node_name: String,
+    /// whether the registered tba is this entry's tba
+    pub matches: bool,
+}
+
+/// A reseller entry's on-chain state. A field is `None` when its call failed, with
+/// the reason in `errors`, e.g. because the tba doesn't use the reseller implementation.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ResellerContractInfo {
+    pub name: String,
+    pub namehash: String,
+    pub tba: Address,
+    /// implementation from the entry's `Gene` log, if the index has seen one
+    pub gene: Option<Address>,
+    pub is_reseller_impl: bool,
+    pub owner: Option<Address>,
+    pub parent_tba: Option<Address>,
+    pub token: Option<TokenBinding>,
+    pub initialized: Option<bool>,
+    pub registration: Option<RegistryRegistration>,
+    pub errors: Vec<String>,
+}
+
+/// Read `owner`, `parentTba`, `token` and `initialized` from the entry's tba and its
+/// registration from the ApiRegistry.
+pub fn get_reseller_contract_info(
+    state: &mut ResellerState,
+    request: GetResellerContractInfo,
+) -> anyhow::Result<ResellerContractInfo> {
+    let namehash = state
+        .db
+        .namehash_of(&request.name)?
+        .ok_or_else(|| anyhow::anyhow!("Name not found: {}", request.name))?;
+    let ownership = state.resolve_ownership(&namehash)?;
+    let tba = ownership
+        .tba
+        .ok_or_else(|| anyhow::anyhow!("{} has no tba", request.name))?;
+    let provider = &state.kimap.provider;
+
+    let mut errors = vec![];
+    let owner = collect(eth_call(provider, tba, Reseller::ownerCall {}), &mut errors).map(|r| r._0);
+    let parent_tba = collect(eth_call(provider, tba, Reseller::parentTbaCall {}), &mut errors).map(|r| r._0);
+    let token = collect(eth_call(provider, tba, Reseller::tokenCall {}), &mut errors).map(|r| TokenBinding {
+        chain_id: r.chainId,
+        token_contract: r.tokenContract,
+        token_id: r.tokenId,
+    });
+    let initialized = collect(eth_call(provider, tba, Reseller::initializedCall {}), &mut errors).map(|r| r._0);
+
+    // registerAsReseller stores resellers by their label under the registry
+    let label = request.name.split('.').next().unwrap_or_default().to_string();
+    let registration = match state.config.api_registry_tba {
+        Some(registry) => collect(
+            eth_call(provider, registry, ApiRegistry::resellersCall { _0: label.clone() }),
+            &mut errors,
+        )
+        .map(|r| RegistryRegistration {
+            registry,
+            label,
+            matches: r.tba == tba,
+            tba: r.tba,
+            node_name: r.node_name,
+        }),
+        None => None,
+    };
+
+    Ok(ResellerContractInfo {
+        name: request.name,
+        namehash,
+        tba,
+        gene: ownership.gene,
+        is_reseller_impl: ownership.gene.is_some() && ownership.gene == state.config.reseller_impl,
+        owner,
+        parent_tba,
+        token,
+        initialized,
+        registration,
+        errors,
+    })
+}
+
+/// The call's result, or `None` with the error appended to `errors`.
+fn collect<T>(result: anyhow::Result<T>, errors: &mut Vec<String>) -> Option<T> {
+    result.map_err(|e| errors.push(e.to_string())).ok()
+}
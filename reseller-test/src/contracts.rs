@@ -0,0 +1,144 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{address, Address, U256};
+use alloy_sol_types::{sol, SolCall};
+use kinode_process_lib::eth;
+
+sol! {
+    /// The ApiRegistry account that resellers register under.
+    interface ApiRegistry {
+        struct ResellerData {
+            address tba;
+            string node_name;
+        }
+
+        function RESSELLER_IMPL() external view returns (address);
+        function getResellerList() external view returns (string[] memory);
+        function getResellers(string[] memory resellersToGet) external view returns (ResellerData[] memory);
+        function resellers(string memory) external view returns (address tba, string memory node_name);
+        function registerAsReseller(string memory reseller_name, string memory full_node_name) external payable returns (address);
+    }
+
+    /// The tba implementation reseller entries use (`reseller_impl`).
+    interface Reseller {
+        function owner() external view returns (address);
+        function parentTba() external view returns (address);
+        /// the ERC-6551 binding of the tba: the kimap token it belongs to
+        function token() external view returns (uint256 chainId, address tokenContract, uint256 tokenId);
+        function initialized() external view returns (bool);
+        function isOperator(address signer) external view returns (bool);
+        function addApiSpec(string memory noteLabel, bytes memory apiSpec) external returns (bytes32);
+        function execute(address to, uint256 value, bytes calldata data, uint8 operation) external payable returns (bytes memory returnData);
+    }
+
+    /// The payment token.
+    interface ERC20 {
+        event Transfer(address indexed from, address indexed to, uint256 value);
+
+        function name() external view returns (string memory);
+        function symbol() external view returns (string memory);
+        function decimals() external view returns (uint8);
+        function balanceOf(address owner) external view returns (uint256);
+        function transfer(address to, uint256 value) external returns (bool);
+    }
+
+    /// Signature validation for contract accounts, such as a buyer's tba.
+    interface ERC1271 {
+        function isValidSignature(bytes32 hash, bytes memory signature) external view returns (bytes4 magicValue);
+    }
+
+    /// The ERC-6551 registry, which creates every kimap entry's tba when it is minted.
+    interface ERC6551Registry {
+        event ERC6551AccountCreated(
+            address account,
+            address indexed implementation,
+            bytes32 salt,
+            uint256 chainId,
+            address indexed tokenContract,
+            uint256 indexed tokenId
+        );
+    }
+
+    /// kimap's `note`, for calling through a tba's `execute`.
+    interface KimapNotes {
+        function note(bytes calldata note, bytes calldata data) external returns (bytes32 notehash);
+    }
+}
+
+/// Canonical ERC-6551 registry address, the same on every chain.
+pub const ERC6551_REGISTRY: Address = address!("000000006551c19487814612e58FE06813775758");
+
+/// A transaction for the user's wallet to sign and send.
+///
+/// # Example
+/// ```
+/// let tx = UnsignedTransaction {
+///     chain_id: 10,
+///     to: tba,
+///     value: U256::ZERO,
+///     data: "0x2a233437...".to_string(),
+///     description: "addApiSpec(~llm-proxy) on zapi-registry1111.test-api-registry.os".to_string(),
+/// };
+/// ```
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
+pub struct UnsignedTransaction {
+    pub chain_id: u64,
+    pub to: Address,
+    pub value: U256,
+    /// ABI-encoded calldata, 0x-prefixed hex
+    pub data: String,
+    pub description: String,
+}
+
+impl UnsignedTransaction {
+    pub fn new<C: SolCall>(chain_id: u64, to: Address, call: C, description: String) -> Self {
+        UnsignedTransaction {
+            chain_id,
+            to,
+            value: U256::ZERO,
+            data: alloy_primitives::hex::encode_prefixed(call.abi_encode()),
+            description,
+        }
+    }
+}
+
+/// `execute(kimap, 0, note(label, data), CALL)` on a tba, which writes a note on its entry.
+pub fn note_transaction(
+    chain_id: u64,
+    kimap: Address,
+    tba: Address,
+    label: &str,
+    data: Vec<u8>,
+    description: String,
+) -> UnsignedTransaction {
+    let note = KimapNotes::noteCall {
+        note: label.as_bytes().to_vec().into(),
+        data: data.into(),
+    };
+    UnsignedTransaction::new(
+        chain_id,
+        tba,
+        Reseller::executeCall {
+            to: kimap,
+            value: U256::ZERO,
+            data: note.abi_encode().into(),
+            operation: 0,
+        },
+        description,
+    )
+}
+
+/// Call a view function and decode its return value.
+pub fn eth_call<C: SolCall>(provider: &eth::Provider, to: Address, call: C) -> anyhow::Result<C::Return> {
+    let tx = eth::TransactionRequest::default()
+        .to(to)
+        .input(eth::TransactionInput::new(call.abi_encode().into()));
+    let bytes = provider
+        .call(tx, None)
+        .map_err(|e| anyhow::anyhow!("{} on {to} failed: {e:?}", C::SIGNATURE))?;
+    C::abi_decode_returns(&bytes, false)
+        .map_err(|e| anyhow::anyhow!("failed to decode return of {} from {to}: {e}", C::SIGNATURE))
+}
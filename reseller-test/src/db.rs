@@ -0,0 +1,1021 @@
+use std::cell::Cell;
+use std::collections::{
+    HashMap,
+    BTreeMap,
+    BTreeSet
+};
+use std::str::FromStr;
+use serde_json::{
+    json,
+    Value
+};
+use alloy_primitives::{Address, Bytes, B256, U256};
+use kinode_process_lib::{
+    our,
+    kiprintln,
+    kimap,
+    sqlite::{self, Sqlite}
+};
+
+use crate::catalog::{
+    ApiCatalogEntry,
+    ApiSpec
+};
+use crate::directory::ResellerEntry;
+use crate::payments::ConsumedPayment;
+use crate::usage_roots::IndexedUsageRoot;
+use crate::receipts::{
+    ListReceipts,
+    SignedReceipt
+};
+use crate::vouchers::{
+    SignedVoucher,
+    StoredVoucher
+};
+use crate::structs::{
+    DataKey,
+    DataKeyKind,
+    DataVersion,
+    IndexChange,
+    NameEntry,
+    NameMatch,
+    Node,
+    OwnedName,
+    Ownership
+};
+
+/// Statements run on every open; each one must be idempotent.
+///
+/// Versions and changes are unique by log position so that replaying logs the
+/// index already holds (e.g. after the process state is reset) is harmless.
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS nodes (
+        namehash TEXT PRIMARY KEY,
+        parent_hash TEXT,
+        name TEXT NOT NULL,
+        parent_path TEXT NOT NULL,
+        full_name TEXT NOT NULL UNIQUE
+    )",
+    "CREATE INDEX IF NOT EXISTS nodes_by_parent ON nodes (parent_hash, full_name)",
+    "CREATE TABLE IF NOT EXISTS data_keys (
+        namehash TEXT NOT NULL,
+        label TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        PRIMARY KEY (namehash, label)
+    )",
+    "CREATE TABLE IF NOT EXISTS data_key_hashes (
+        keyhash TEXT PRIMARY KEY,
+        namehash TEXT NOT NULL,
+        label TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS note_versions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        namehash TEXT NOT NULL,
+        label TEXT NOT NULL,
+        data TEXT NOT NULL,
+        block INTEGER,
+        tx_hash TEXT,
+        log_index INTEGER,
+        timestamp INTEGER,
+        UNIQUE (block, log_index)
+    )",
+    "CREATE INDEX IF NOT EXISTS note_versions_by_key ON note_versions (namehash, label, id)",
+    "CREATE TABLE IF NOT EXISTS ownership (
+        namehash TEXT PRIMARY KEY,
+        owner TEXT,
+        gene TEXT,
+        tba TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS ownership_by_owner ON ownership (owner)",
+    "CREATE INDEX IF NOT EXISTS ownership_by_gene ON ownership (gene)",
+    "CREATE INDEX IF NOT EXISTS ownership_by_tba ON ownership (tba)",
+    "CREATE TABLE IF NOT EXISTS resellers (
+        tba TEXT PRIMARY KEY,
+        namehash TEXT,
+        name TEXT NOT NULL,
+        owner TEXT,
+        node_name TEXT,
+        offerings TEXT NOT NULL,
+        registered INTEGER NOT NULL,
+        error TEXT
+    )",
+    "CREATE TABLE IF NOT EXISTS api_specs (
+        namehash TEXT NOT NULL,
+        label TEXT NOT NULL,
+        reseller TEXT NOT NULL,
+        block INTEGER,
+        spec TEXT NOT NULL,
+        PRIMARY KEY (namehash, label)
+    )",
+    "CREATE TABLE IF NOT EXISTS usage_roots (
+        namehash TEXT NOT NULL,
+        period INTEGER NOT NULL,
+        reseller TEXT NOT NULL,
+        root TEXT NOT NULL,
+        note TEXT NOT NULL,
+        block INTEGER,
+        PRIMARY KEY (namehash, period)
+    )",
+    "CREATE TABLE IF NOT EXISTS changes (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        block INTEGER NOT NULL,
+        log_index INTEGER,
+        namehash TEXT NOT NULL,
+        name TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        UNIQUE (block, log_index)
+    )",
+];
+
+/// Name of the database holding payments, vouchers and receipts. Unlike the index it
+/// is not named after the chain and contract, so a config change never drops it.
+const LEDGER_DB_NAME: &str = "ledger";
+
+/// Statements run on every open of the ledger; each one must be idempotent.
+const LEDGER_SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS consumed_payments (
+        tx_hash TEXT PRIMARY KEY,
+        payer TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        block INTEGER NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS vouchers (
+        payer TEXT PRIMARY KEY,
+        payee TEXT NOT NULL,
+        token TEXT NOT NULL,
+        cumulative_amount TEXT NOT NULL,
+        signature TEXT NOT NULL,
+        charged TEXT NOT NULL,
+        calls INTEGER NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS receipts (
+        request_id TEXT PRIMARY KEY,
+        reseller TEXT NOT NULL,
+        customer TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        receipt TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS receipts_by_customer ON receipts (customer, timestamp)",
+];
+
+/// Ledger tables older versions kept in the index database, with their columns; they
+/// are moved to the ledger on open.
+const LEDGER_TABLES: &[(&str, &[&str])] = &[
+    ("consumed_payments", &["tx_hash", "payer", "amount", "block"]),
+    ("vouchers", &["payer", "payee", "token", "cumulative_amount", "signature", "charged", "calls"]),
+    ("receipts", &["request_id", "reseller", "customer", "timestamp", "receipt"]),
+];
+
+/// Tables carried in an index snapshot, with the columns to copy. Row ids are left out
+/// so an import appends cleanly; rows are dumped in id order, which keeps note versions
+/// in their original order. The change feed is local history and isn't exported.
+pub const SNAPSHOT_TABLES: &[(&str, &[&str])] = &[
+    ("nodes", &["namehash", "parent_hash", "name", "parent_path", "full_name"]),
+    ("data_keys", &["namehash", "label", "kind"]),
+    ("data_key_hashes", &["keyhash", "namehash", "label"]),
+    ("note_versions", &["namehash", "label", "data", "block", "tx_hash", "log_index", "timestamp"]),
+    ("ownership", &["namehash", "owner", "gene", "tba"]),
+    ("api_specs", &["namehash", "label", "reseller", "block", "spec"]),
+    ("usage_roots", &["namehash", "period", "reseller", "root", "note", "block"]),
+];
+
+/// The kimap index, stored in the sqlite service, and the ledger of payments, vouchers
+/// and receipts next to it.
+///
+/// Not part of the serialized process state: the handles are reopened on every start.
+#[derive(Debug, Default)]
+pub struct KimapDb {
+    sqlite: Option<Sqlite>,
+    ledger: Option<Sqlite>,
+    /// Open index transaction, which index writes are buffered in until `commit`.
+    tx: Cell<Option<u64>>,
+}
+
+/// A row of the `nodes` table.
+#[derive(Debug, Clone)]
+pub struct NodeRow {
+    pub namehash: String,
+    pub name: String,
+    pub parent_path: String,
+    pub full_name: String,
+}
+
+impl KimapDb {
+    /// Open (creating if needed) the index database and make sure the root node exists.
+    pub fn open(&mut self, db_name: &str) -> anyhow::Result<()> {
+        let sqlite = sqlite::open(our().package_id(), db_name, None)?;
+        for statement in SCHEMA {
+            sqlite.write(statement.to_string(), vec![], None)?;
+        }
+        self.sqlite = Some(sqlite);
+        self.write(
+            "INSERT OR IGNORE INTO nodes (namehash, parent_hash, name, parent_path, full_name)
+             VALUES (?1, NULL, '', '', '')",
+            vec![json!(kimap::KIMAP_ROOT_HASH)],
+        )?;
+        // the directory cache predates its error column
+        if self
+            .read("SELECT 1 FROM pragma_table_info('resellers') WHERE name = 'error'", vec![])?
+            .is_empty()
+        {
+            self.write("ALTER TABLE resellers ADD COLUMN error TEXT", vec![])?;
+        }
+
+        let ledger = sqlite::open(our().package_id(), LEDGER_DB_NAME, None)?;
+        for statement in LEDGER_SCHEMA {
+            ledger.write(statement.to_string(), vec![], None)?;
+        }
+        self.ledger = Some(ledger);
+        self.move_ledger_tables()
+    }
+
+    /// Move payments, vouchers and receipts an older version stored in the index into
+    /// the ledger, then drop them from the index.
+    fn move_ledger_tables(&self) -> anyhow::Result<()> {
+        for (table, columns) in LEDGER_TABLES {
+            let exists = !self
+                .read(
+                    "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                    vec![json!(table)],
+                )?
+                .is_empty();
+            if !exists {
+                continue;
+            }
+            let rows = self.read(&format!("SELECT {} FROM {table}", columns.join(", ")), vec![])?;
+            let statement = insert_statement(table, columns);
+            for row in &rows {
+                self.ledger_write(&statement, row_params(columns, row))?;
+            }
+            self.write(&format!("DROP TABLE {table}"), vec![])?;
+            kiprintln!("moved {} {table} rows from the index to the ledger", rows.len());
+        }
+        Ok(())
+    }
+
+    fn sqlite(&self) -> anyhow::Result<&Sqlite> {
+        self.sqlite
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("kimap index database is not open"))
+    }
+
+    fn read(&self, query: &str, params: Vec<Value>) -> anyhow::Result<Vec<HashMap<String, Value>>> {
+        self.sqlite()?.read(query.to_string(), params)
+    }
+
+    fn write(&self, statement: &str, params: Vec<Value>) -> anyhow::Result<()> {
+        self.sqlite()?.write(statement.to_string(), params, self.tx.get())
+    }
+
+    /// Buffer index writes in one transaction until `commit`. Reads don't see them
+    /// until then. Ledger writes are not part of the transaction.
+    pub fn begin(&self) -> anyhow::Result<()> {
+        let tx = self.sqlite()?.begin_tx()?;
+        self.tx.set(Some(tx));
+        Ok(())
+    }
+
+    pub fn commit(&self) -> anyhow::Result<()> {
+        match self.tx.take() {
+            Some(tx) => self.sqlite()?.commit_tx(tx),
+            None => Ok(()),
+        }
+    }
+
+    /// Drop the open transaction without applying its writes.
+    pub fn discard(&self) {
+        self.tx.set(None);
+    }
+
+    fn ledger(&self) -> anyhow::Result<&Sqlite> {
+        self.ledger
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("ledger database is not open"))
+    }
+
+    fn ledger_read(&self, query: &str, params: Vec<Value>) -> anyhow::Result<Vec<HashMap<String, Value>>> {
+        self.ledger()?.read(query.to_string(), params)
+    }
+
+    fn ledger_write(&self, statement: &str, params: Vec<Value>) -> anyhow::Result<()> {
+        self.ledger()?.write(statement.to_string(), params, None)
+    }
+
+    // nodes
+
+    pub fn insert_node(&self, namehash: &str, parent_hash: &str, name: &str, parent_path: &str, full_name: &str) -> anyhow::Result<()> {
+        self.write(
+            "INSERT OR IGNORE INTO nodes (namehash, parent_hash, name, parent_path, full_name)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            vec![json!(namehash), json!(parent_hash), json!(name), json!(parent_path), json!(full_name)],
+        )
+    }
+
+    pub fn node_row(&self, namehash: &str) -> anyhow::Result<Option<NodeRow>> {
+        let rows = self.read(
+            "SELECT namehash, name, parent_path, full_name FROM nodes WHERE namehash = ?1",
+            vec![json!(namehash)],
+        )?;
+        Ok(rows.first().map(node_row))
+    }
+
+    pub fn namehash_of(&self, full_name: &str) -> anyhow::Result<Option<String>> {
+        let rows = self.read(
+            "SELECT namehash FROM nodes WHERE full_name = ?1",
+            vec![json!(full_name)],
+        )?;
+        Ok(rows.first().and_then(|row| text(row, "namehash")))
+    }
+
+    /// A node with all its child names and data keys.
+    pub fn get_node(&self, namehash: &str) -> anyhow::Result<Option<Node>> {
+        let Some(row) = self.node_row(namehash)? else {
+            return Ok(None);
+        };
+
+        let child_names: BTreeSet<String> = self
+            .read(
+                "SELECT full_name FROM nodes WHERE parent_hash = ?1",
+                vec![json!(namehash)],
+            )?
+            .iter()
+            .filter_map(|row| text(row, "full_name"))
+            .collect();
+
+        let mut data_keys = BTreeMap::new();
+        for key in self.read(
+            "SELECT label FROM data_keys WHERE namehash = ?1",
+            vec![json!(namehash)],
+        )? {
+            let Some(label) = text(&key, "label") else {
+                continue;
+            };
+            if let Some(data_key) = self.data_key(namehash, &label)? {
+                data_keys.insert(label, data_key);
+            }
+        }
+
+        Ok(Some(Node {
+            parent_path: row.parent_path,
+            name: row.name,
+            child_names,
+            data_keys,
+        }))
+    }
+
+    pub fn child_count(&self, namehash: &str) -> anyhow::Result<usize> {
+        let rows = self.read(
+            "SELECT COUNT(*) AS count FROM nodes WHERE parent_hash = ?1",
+            vec![json!(namehash)],
+        )?;
+        Ok(rows.first().and_then(|row| int(row, "count")).unwrap_or(0) as usize)
+    }
+
+    /// A page of a node's children, in name order.
+    pub fn children(&self, namehash: &str, offset: usize, limit: usize) -> anyhow::Result<Vec<NameEntry>> {
+        Ok(self
+            .read(
+                "SELECT namehash, full_name FROM nodes WHERE parent_hash = ?1
+                 ORDER BY full_name LIMIT ?2 OFFSET ?3",
+                vec![json!(namehash), json!(limit), json!(offset)],
+            )?
+            .iter()
+            .filter_map(name_entry)
+            .collect())
+    }
+
+    pub fn search_names(&self, query: &str, mode: NameMatch, limit: usize) -> anyhow::Result<Vec<NameEntry>> {
+        let escaped = query
+            .to_lowercase()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = match mode {
+            NameMatch::Prefix => format!("{escaped}%"),
+            NameMatch::Substring => format!("%{escaped}%"),
+        };
+        // the root is the empty name and matches every query
+        Ok(self
+            .read(
+                "SELECT namehash, full_name FROM nodes
+                 WHERE full_name != '' AND full_name LIKE ?1 ESCAPE '\\'
+                 ORDER BY full_name LIMIT ?2",
+                vec![json!(pattern), json!(limit)],
+            )?
+            .iter()
+            .filter_map(name_entry)
+            .collect())
+    }
+
+    // data keys
+
+    pub fn data_key_kind(&self, namehash: &str, label: &str) -> anyhow::Result<Option<DataKeyKind>> {
+        let rows = self.read(
+            "SELECT kind FROM data_keys WHERE namehash = ?1 AND label = ?2",
+            vec![json!(namehash), json!(label)],
+        )?;
+        Ok(rows
+            .first()
+            .and_then(|row| text(row, "kind"))
+            .and_then(|kind| serde_json::from_value(json!(kind)).ok()))
+    }
+
+    /// All versions of a data key, oldest first.
+    pub fn data_key(&self, namehash: &str, label: &str) -> anyhow::Result<Option<DataKey>> {
+        let Some(kind) = self.data_key_kind(namehash, label)? else {
+            return Ok(None);
+        };
+        let mut versions: Vec<DataVersion> = self
+            .read(
+                "SELECT data, block, tx_hash, log_index, timestamp FROM note_versions
+                 WHERE namehash = ?1 AND label = ?2 ORDER BY id",
+                vec![json!(namehash), json!(label)],
+            )?
+            .iter()
+            .filter_map(data_version)
+            .collect();
+
+        Ok(match kind {
+            DataKeyKind::Note if !versions.is_empty() => Some(DataKey::Note(versions)),
+            DataKeyKind::Fact if !versions.is_empty() => Some(DataKey::Fact(versions.remove(0))),
+            _ => None,
+        })
+    }
+
+    pub fn insert_version(&self, namehash: &str, label: &str, kind: DataKeyKind, version: &DataVersion) -> anyhow::Result<()> {
+        self.write(
+            "INSERT OR IGNORE INTO data_keys (namehash, label, kind) VALUES (?1, ?2, ?3)",
+            vec![json!(namehash), json!(label), json!(kind)],
+        )?;
+        self.write(
+            "INSERT OR IGNORE INTO note_versions (namehash, label, data, block, tx_hash, log_index, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            vec![
+                json!(namehash),
+                json!(label),
+                json!(version.data),
+                json!(version.block),
+                json!(version.tx_hash),
+                json!(version.log_index),
+                json!(version.timestamp),
+            ],
+        )
+    }
+
+    /// The latest version of every note whose label starts with `prefix`, as
+    /// `(namehash, label, version)`, oldest first.
+    pub fn latest_notes(&self, prefix: &str) -> anyhow::Result<Vec<(String, String, DataVersion)>> {
+        Ok(self
+            .read(
+                "SELECT v.namehash, v.label, v.data, v.block, v.tx_hash, v.log_index, v.timestamp
+                 FROM note_versions v JOIN data_keys k ON k.namehash = v.namehash AND k.label = v.label
+                 WHERE k.kind = ?2 AND v.id IN (
+                     SELECT MAX(id) FROM note_versions WHERE label LIKE ?1 GROUP BY namehash, label
+                 )
+                 ORDER BY v.id",
+                vec![json!(format!("{prefix}%")), json!(DataKeyKind::Note)],
+            )?
+            .iter()
+            .filter_map(|row| Some((text(row, "namehash")?, text(row, "label")?, data_version(row)?)))
+            .collect())
+    }
+
+    /// Every version of every note with this label, as `(namehash, version)`, oldest first.
+    pub fn note_versions_labelled(&self, label: &str) -> anyhow::Result<Vec<(String, DataVersion)>> {
+        Ok(self
+            .read(
+                "SELECT v.namehash, v.data, v.block, v.tx_hash, v.log_index, v.timestamp
+                 FROM note_versions v JOIN data_keys k ON k.namehash = v.namehash AND k.label = v.label
+                 WHERE v.label = ?1 AND k.kind = ?2
+                 ORDER BY v.id",
+                vec![json!(label), json!(DataKeyKind::Note)],
+            )?
+            .iter()
+            .filter_map(|row| Some((text(row, "namehash")?, data_version(row)?)))
+            .collect())
+    }
+
+    /// Remember the kimap namehash of a note or fact, so it can be looked up like an entry.
+    pub fn set_data_key_hash(&self, keyhash: &str, namehash: &str, label: &str) -> anyhow::Result<()> {
+        self.write(
+            "INSERT OR IGNORE INTO data_key_hashes (keyhash, namehash, label) VALUES (?1, ?2, ?3)",
+            vec![json!(keyhash), json!(namehash), json!(label)],
+        )
+    }
+
+    /// The entry and label of the note or fact with this kimap namehash.
+    pub fn data_key_by_hash(&self, keyhash: &str) -> anyhow::Result<Option<(String, String)>> {
+        let rows = self.read(
+            "SELECT namehash, label FROM data_key_hashes WHERE keyhash = ?1",
+            vec![json!(keyhash)],
+        )?;
+        Ok(rows
+            .first()
+            .and_then(|row| Some((text(row, "namehash")?, text(row, "label")?))))
+    }
+
+    // ownership
+
+    pub fn get_ownership(&self, namehash: &str) -> anyhow::Result<Option<Ownership>> {
+        let rows = self.read(
+            "SELECT owner, gene, tba FROM ownership WHERE namehash = ?1",
+            vec![json!(namehash)],
+        )?;
+        Ok(rows.first().map(|row| Ownership {
+            owner: address(row, "owner"),
+            gene: address(row, "gene"),
+            tba: address(row, "tba"),
+        }))
+    }
+
+    pub fn set_gene(&self, namehash: &str, gene: Address) -> anyhow::Result<()> {
+        self.write(
+            "INSERT INTO ownership (namehash, gene) VALUES (?1, ?2)
+             ON CONFLICT (namehash) DO UPDATE SET gene = excluded.gene",
+            vec![json!(namehash), json!(format!("{gene:#x}"))],
+        )
+    }
+
+    /// `None` clears the owner, e.g. after a burn.
+    pub fn set_owner(&self, namehash: &str, owner: Option<Address>) -> anyhow::Result<()> {
+        self.write(
+            "INSERT INTO ownership (namehash, owner) VALUES (?1, ?2)
+             ON CONFLICT (namehash) DO UPDATE SET owner = excluded.owner",
+            vec![json!(namehash), json!(owner.map(|owner| format!("{owner:#x}")))],
+        )
+    }
+
+    pub fn set_tba(&self, namehash: &str, tba: Address) -> anyhow::Result<()> {
+        self.write(
+            "INSERT INTO ownership (namehash, tba) VALUES (?1, ?2)
+             ON CONFLICT (namehash) DO UPDATE SET tba = excluded.tba",
+            vec![json!(namehash), json!(format!("{tba:#x}"))],
+        )
+    }
+
+    pub fn names_by_owner(&self, owner: Address) -> anyhow::Result<Vec<OwnedName>> {
+        Ok(self
+            .read(
+                "SELECT nodes.namehash, nodes.full_name, ownership.tba, ownership.gene
+                 FROM ownership JOIN nodes ON nodes.namehash = ownership.namehash
+                 WHERE ownership.owner = ?1 ORDER BY nodes.full_name",
+                vec![json!(format!("{owner:#x}"))],
+            )?
+            .iter()
+            .filter_map(|row| {
+                Some(OwnedName {
+                    name: text(row, "full_name")?,
+                    namehash: text(row, "namehash")?,
+                    tba: address(row, "tba"),
+                    gene: address(row, "gene"),
+                })
+            })
+            .collect())
+    }
+
+    /// The entry a tba belongs to, if its tba has been resolved.
+    pub fn name_by_tba(&self, tba: Address) -> anyhow::Result<Option<NameEntry>> {
+        let rows = self.read(
+            "SELECT nodes.namehash, nodes.full_name
+             FROM ownership JOIN nodes ON nodes.namehash = ownership.namehash
+             WHERE ownership.tba = ?1",
+            vec![json!(format!("{tba:#x}"))],
+        )?;
+        Ok(rows.first().and_then(name_entry))
+    }
+
+    /// Every indexed entry whose tba uses the given implementation.
+    pub fn names_by_gene(&self, gene: Address) -> anyhow::Result<Vec<NameEntry>> {
+        Ok(self
+            .read(
+                "SELECT nodes.namehash, nodes.full_name
+                 FROM ownership JOIN nodes ON nodes.namehash = ownership.namehash
+                 WHERE ownership.gene = ?1 ORDER BY nodes.full_name",
+                vec![json!(format!("{gene:#x}"))],
+            )?
+            .iter()
+            .filter_map(name_entry)
+            .collect())
+    }
+
+    // reseller directory
+
+    /// Replace the whole reseller directory.
+    pub fn replace_resellers(&self, resellers: &[ResellerEntry]) -> anyhow::Result<()> {
+        self.write("DELETE FROM resellers", vec![])?;
+        for reseller in resellers {
+            self.write(
+                "INSERT OR REPLACE INTO resellers (tba, namehash, name, owner, node_name, offerings, registered, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                vec![
+                    json!(format!("{:#x}", reseller.tba)),
+                    json!(reseller.namehash),
+                    json!(reseller.name),
+                    json!(reseller.owner.map(|owner| format!("{owner:#x}"))),
+                    json!(reseller.node_name),
+                    json!(serde_json::to_string(&reseller.offerings)?),
+                    json!(reseller.registered),
+                    json!(reseller.error),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn list_resellers(&self) -> anyhow::Result<Vec<ResellerEntry>> {
+        Ok(self
+            .read(
+                "SELECT tba, namehash, name, owner, node_name, offerings, registered, error
+                 FROM resellers ORDER BY name",
+                vec![],
+            )?
+            .iter()
+            .filter_map(|row| {
+                Some(ResellerEntry {
+                    name: text(row, "name")?,
+                    namehash: text(row, "namehash"),
+                    tba: address(row, "tba")?,
+                    owner: address(row, "owner"),
+                    node_name: text(row, "node_name"),
+                    offerings: serde_json::from_str(&text(row, "offerings")?).ok()?,
+                    registered: int(row, "registered")? != 0,
+                    error: text(row, "error"),
+                })
+            })
+            .collect())
+    }
+
+    // api catalog
+
+    pub fn has_api_specs(&self) -> anyhow::Result<bool> {
+        Ok(!self.read("SELECT 1 FROM api_specs LIMIT 1", vec![])?.is_empty())
+    }
+
+    /// Store the latest spec written under a note label. Replaying an older version
+    /// of the note leaves a newer spec in place.
+    pub fn upsert_api_spec(&self, namehash: &str, label: &str, reseller: &str, block: Option<u64>, spec: &Value) -> anyhow::Result<()> {
+        self.write(
+            "INSERT INTO api_specs (namehash, label, reseller, block, spec) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (namehash, label) DO UPDATE SET block = excluded.block, spec = excluded.spec
+             WHERE api_specs.block IS NULL OR excluded.block >= api_specs.block",
+            vec![
+                json!(namehash),
+                json!(label),
+                json!(reseller),
+                json!(block),
+                json!(serde_json::to_string(spec)?),
+            ],
+        )
+    }
+
+    /// Remove the spec under a note label, unless it is newer than the version that
+    /// replaced it.
+    pub fn delete_api_spec(&self, namehash: &str, label: &str, block: Option<u64>) -> anyhow::Result<()> {
+        self.write(
+            "DELETE FROM api_specs
+             WHERE namehash = ?1 AND label = ?2 AND (block IS NULL OR ?3 IS NULL OR ?3 >= block)",
+            vec![json!(namehash), json!(label), json!(block)],
+        )
+    }
+
+    pub fn api_specs(&self) -> anyhow::Result<Vec<ApiSpec>> {
+        Ok(self
+            .read(
+                "SELECT namehash, label, reseller, block, spec FROM api_specs ORDER BY reseller, label",
+                vec![],
+            )?
+            .iter()
+            .filter_map(api_spec)
+            .collect())
+    }
+
+    pub fn api_spec(&self, namehash: &str, label: &str) -> anyhow::Result<Option<ApiSpec>> {
+        let rows = self.read(
+            "SELECT namehash, label, reseller, block, spec FROM api_specs
+             WHERE namehash = ?1 AND label = ?2",
+            vec![json!(namehash), json!(label)],
+        )?;
+        Ok(rows.first().and_then(api_spec))
+    }
+
+    // payments
+
+    pub fn payment_consumed(&self, tx_hash: &B256) -> anyhow::Result<bool> {
+        let rows = self.ledger_read(
+            "SELECT 1 FROM consumed_payments WHERE tx_hash = ?1",
+            vec![json!(tx_hash.to_string())],
+        )?;
+        Ok(!rows.is_empty())
+    }
+
+    /// Fails if the payment was already consumed.
+    pub fn insert_consumed_payment(&self, payment: &ConsumedPayment) -> anyhow::Result<()> {
+        self.ledger_write(
+            "INSERT INTO consumed_payments (tx_hash, payer, amount, block) VALUES (?1, ?2, ?3, ?4)",
+            vec![
+                json!(payment.tx_hash.to_string()),
+                json!(format!("{:#x}", payment.payer)),
+                json!(payment.amount.to_string()),
+                json!(payment.block),
+            ],
+        )
+    }
+
+    /// The latest voucher from a payer.
+    pub fn voucher(&self, payer: &Address) -> anyhow::Result<Option<StoredVoucher>> {
+        let rows = self.ledger_read(
+            "SELECT * FROM vouchers WHERE payer = ?1",
+            vec![json!(format!("{:#x}", payer))],
+        )?;
+        Ok(rows.iter().find_map(stored_voucher))
+    }
+
+    pub fn vouchers(&self) -> anyhow::Result<Vec<StoredVoucher>> {
+        let rows = self.ledger_read("SELECT * FROM vouchers ORDER BY payer", vec![])?;
+        Ok(rows.iter().filter_map(stored_voucher).collect())
+    }
+
+    /// Replace a payer's voucher with a newer one.
+    pub fn upsert_voucher(&self, stored: &StoredVoucher) -> anyhow::Result<()> {
+        let voucher = &stored.voucher;
+        self.ledger_write(
+            "INSERT INTO vouchers (payer, payee, token, cumulative_amount, signature, charged, calls)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(payer) DO UPDATE SET
+                payee = excluded.payee,
+                token = excluded.token,
+                cumulative_amount = excluded.cumulative_amount,
+                signature = excluded.signature,
+                charged = excluded.charged,
+                calls = excluded.calls",
+            vec![
+                json!(format!("{:#x}", voucher.payer)),
+                json!(format!("{:#x}", voucher.payee)),
+                json!(format!("{:#x}", voucher.token)),
+                json!(voucher.cumulative_amount.to_string()),
+                json!(voucher.signature.to_string()),
+                json!(stored.charged.to_string()),
+                json!(stored.calls),
+            ],
+        )
+    }
+
+    // receipts
+
+    pub fn insert_receipt(&self, signed: &SignedReceipt) -> anyhow::Result<()> {
+        let receipt = &signed.receipt;
+        self.ledger_write(
+            "INSERT OR IGNORE INTO receipts (request_id, reseller, customer, timestamp, receipt)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            vec![
+                json!(receipt.request_id),
+                json!(receipt.reseller),
+                json!(receipt.customer),
+                json!(receipt.timestamp),
+                json!(serde_json::to_string(signed)?),
+            ],
+        )
+    }
+
+    /// Receipts matching the filter, oldest first.
+    pub fn receipts(&self, filter: &ListReceipts) -> anyhow::Result<Vec<SignedReceipt>> {
+        let rows = self.ledger_read(
+            "SELECT receipt FROM receipts
+             WHERE (?1 IS NULL OR customer = ?1)
+               AND (?2 IS NULL OR reseller = ?2)
+               AND (?3 IS NULL OR timestamp >= ?3)
+               AND (?4 IS NULL OR timestamp < ?4)
+             ORDER BY timestamp, request_id",
+            vec![
+                json!(filter.customer),
+                json!(filter.reseller),
+                json!(filter.from),
+                json!(filter.to),
+            ],
+        )?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| serde_json::from_str(&text(row, "receipt")?).ok())
+            .collect())
+    }
+
+    pub fn receipt(&self, request_id: &str) -> anyhow::Result<Option<SignedReceipt>> {
+        let rows = self.ledger_read(
+            "SELECT receipt FROM receipts WHERE request_id = ?1",
+            vec![json!(request_id)],
+        )?;
+        Ok(rows
+            .iter()
+            .find_map(|row| serde_json::from_str(&text(row, "receipt")?).ok()))
+    }
+
+    // usage roots
+
+    pub fn has_usage_roots(&self) -> anyhow::Result<bool> {
+        Ok(!self.read("SELECT 1 FROM usage_roots LIMIT 1", vec![])?.is_empty())
+    }
+
+    /// A reseller may republish a period; the newer note wins.
+    pub fn upsert_usage_root(&self, root: &IndexedUsageRoot) -> anyhow::Result<()> {
+        self.write(
+            "INSERT INTO usage_roots (namehash, period, reseller, root, note, block) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (namehash, period) DO UPDATE SET root = excluded.root, note = excluded.note, block = excluded.block
+             WHERE usage_roots.block IS NULL OR excluded.block >= usage_roots.block",
+            vec![
+                json!(root.namehash),
+                json!(root.note.period),
+                json!(root.reseller),
+                json!(root.note.root.to_string()),
+                json!(serde_json::to_string(&root.note)?),
+                json!(root.block),
+            ],
+        )
+    }
+
+    /// Indexed usage roots, of one entry or of all, newest period first.
+    pub fn usage_roots(&self, namehash: Option<&str>) -> anyhow::Result<Vec<IndexedUsageRoot>> {
+        Ok(self
+            .read(
+                "SELECT namehash, reseller, note, block FROM usage_roots
+                 WHERE ?1 IS NULL OR namehash = ?1
+                 ORDER BY period DESC, reseller",
+                vec![json!(namehash)],
+            )?
+            .iter()
+            .filter_map(usage_root)
+            .collect())
+    }
+
+    pub fn usage_root(&self, namehash: &str, period: u64) -> anyhow::Result<Option<IndexedUsageRoot>> {
+        let rows = self.read(
+            "SELECT namehash, reseller, note, block FROM usage_roots WHERE namehash = ?1 AND period = ?2",
+            vec![json!(namehash), json!(period)],
+        )?;
+        Ok(rows.iter().find_map(usage_root))
+    }
+
+    // snapshots
+
+    /// Every row of a snapshot table, limited to the given columns.
+    pub fn dump_table(&self, table: &str, columns: &[&str]) -> anyhow::Result<Vec<HashMap<String, Value>>> {
+        let order = if table == "note_versions" { "id" } else { "rowid" };
+        self.read(
+            &format!("SELECT {} FROM {table} ORDER BY {order}", columns.join(", ")),
+            vec![],
+        )
+    }
+
+    /// Insert snapshot rows, keeping whatever the index already holds on conflict.
+    /// Only the given columns are read from each row.
+    pub fn load_rows(&self, table: &str, columns: &[&str], rows: &[HashMap<String, Value>]) -> anyhow::Result<()> {
+        let statement = insert_statement(table, columns);
+        for row in rows {
+            self.write(&statement, row_params(columns, row))?;
+        }
+        Ok(())
+    }
+
+    // change feed
+
+    /// Append a change and drop the oldest ones beyond `max_changes`.
+    pub fn insert_change(&self, change: &IndexChange, max_changes: usize) -> anyhow::Result<()> {
+        self.write(
+            "INSERT OR IGNORE INTO changes (block, log_index, namehash, name, kind)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            vec![
+                json!(change.block),
+                json!(change.log_index),
+                json!(change.namehash),
+                json!(change.name),
+                json!(serde_json::to_string(&change.kind)?),
+            ],
+        )?;
+        self.write(
+            "DELETE FROM changes WHERE id <= (SELECT MAX(id) FROM changes) - ?1",
+            vec![json!(max_changes)],
+        )
+    }
+
+    pub fn change_count(&self) -> anyhow::Result<usize> {
+        let rows = self.read("SELECT COUNT(*) AS count FROM changes", vec![])?;
+        Ok(rows.first().and_then(|row| int(row, "count")).unwrap_or(0) as usize)
+    }
+
+    pub fn oldest_change_block(&self) -> anyhow::Result<Option<u64>> {
+        let rows = self.read("SELECT MIN(block) AS block FROM changes", vec![])?;
+        Ok(rows.first().and_then(|row| int(row, "block")))
+    }
+
+    /// Changes applied after `block`, oldest first.
+    pub fn changes_since(&self, block: u64) -> anyhow::Result<Vec<IndexChange>> {
+        Ok(self
+            .read(
+                "SELECT block, log_index, namehash, name, kind FROM changes
+                 WHERE block > ?1 ORDER BY id",
+                vec![json!(block)],
+            )?
+            .iter()
+            .filter_map(|row| {
+                Some(IndexChange {
+                    block: int(row, "block")?,
+                    log_index: int(row, "log_index"),
+                    namehash: text(row, "namehash")?,
+                    name: text(row, "name")?,
+                    kind: serde_json::from_str(&text(row, "kind")?).ok()?,
+                })
+            })
+            .collect())
+    }
+}
+
+/// `INSERT OR IGNORE` of the given columns, keeping existing rows on conflict.
+fn insert_statement(table: &str, columns: &[&str]) -> String {
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+    format!(
+        "INSERT OR IGNORE INTO {table} ({}) VALUES ({})",
+        columns.join(", "),
+        placeholders.join(", "),
+    )
+}
+
+fn row_params(columns: &[&str], row: &HashMap<String, Value>) -> Vec<Value> {
+    columns
+        .iter()
+        .map(|column| row.get(*column).cloned().unwrap_or(Value::Null))
+        .collect()
+}
+
+fn text(row: &HashMap<String, Value>, column: &str) -> Option<String> {
+    row.get(column)?.as_str().map(str::to_string)
+}
+
+fn int(row: &HashMap<String, Value>, column: &str) -> Option<u64> {
+    row.get(column)?.as_u64()
+}
+
+fn address(row: &HashMap<String, Value>, column: &str) -> Option<Address> {
+    Address::from_str(&text(row, column)?).ok()
+}
+
+fn node_row(row: &HashMap<String, Value>) -> NodeRow {
+    NodeRow {
+        namehash: text(row, "namehash").unwrap_or_default(),
+        name: text(row, "name").unwrap_or_default(),
+        parent_path: text(row, "parent_path").unwrap_or_default(),
+        full_name: text(row, "full_name").unwrap_or_default(),
+    }
+}
+
+fn name_entry(row: &HashMap<String, Value>) -> Option<NameEntry> {
+    Some(NameEntry {
+        name: text(row, "full_name")?,
+        namehash: text(row, "namehash")?,
+    })
+}
+
+fn api_spec(row: &HashMap<String, Value>) -> Option<ApiSpec> {
+    let spec: Value = serde_json::from_str(&text(row, "spec")?).ok()?;
+    Some(ApiSpec {
+        entry: ApiCatalogEntry::new(
+            text(row, "reseller")?,
+            text(row, "namehash")?,
+            text(row, "label")?,
+            int(row, "block"),
+            &spec,
+        ),
+        spec,
+    })
+}
+
+fn stored_voucher(row: &HashMap<String, Value>) -> Option<StoredVoucher> {
+    Some(StoredVoucher {
+        voucher: SignedVoucher {
+            payer: address(row, "payer")?,
+            payee: address(row, "payee")?,
+            token: address(row, "token")?,
+            cumulative_amount: U256::from_str(&text(row, "cumulative_amount")?).ok()?,
+            signature: Bytes::from_str(&text(row, "signature")?).ok()?,
+        },
+        charged: U256::from_str(&text(row, "charged")?).ok()?,
+        calls: int(row, "calls")?,
+    })
+}
+
+fn usage_root(row: &HashMap<String, Value>) -> Option<IndexedUsageRoot> {
+    Some(IndexedUsageRoot {
+        reseller: text(row, "reseller")?,
+        namehash: text(row, "namehash")?,
+        note: serde_json::from_str(&text(row, "note")?).ok()?,
+        block: int(row, "block"),
+    })
+}
+
+fn data_version(row: &HashMap<String, Value>) -> Option<DataVersion> {
+    Some(DataVersion {
+        data: serde_json::from_value(row.get("data")?.clone()).ok()?,
+        block: int(row, "block"),
+        tx_hash: text(row, "tx_hash").and_then(|hash| B256::from_str(&hash).ok()),
+        log_index: int(row, "log_index"),
+        timestamp: int(row, "timestamp"),
+    })
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use serde::{
+    Deserialize,
+    Serialize
+};
+use alloy_primitives::U256;
+
+use crate::catalog::parse_spec;
+use crate::structs::{
+    DataKey,
+    Node
+};
+
+/// A data key's value rendered in its known encoding.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "value")]
+pub enum DecodedData {
+    /// bytes with no better interpretation, as 0x-prefixed hex
+    Hex(String),
+    /// UTF-8 text
+    Text(String),
+    /// a UTF-8 JSON document
+    Json(serde_json::Value),
+    /// an IPv4 or IPv6 address
+    Ip(String),
+    Port(u16),
+    /// a list of 32-byte namehashes, e.g. a node's routers
+    Namehashes(Vec<String>),
+    /// a 32-byte big-endian unsigned integer, in decimal
+    Number(String),
+}
+
+/// Decodes a data key's bytes; `None` if the bytes don't fit the encoding.
+type Decoder = fn(&[u8]) -> Option<DecodedData>;
+
+/// Labels with a known encoding. Anything else goes through [`decode_fallback`].
+const DECODERS: &[(&str, Decoder)] = &[
+    ("~net-key", decode_hex),
+    ("~ip", decode_ip),
+    ("~tcp-port", decode_port),
+    ("~udp-port", decode_port),
+    ("~ws-port", decode_port),
+    ("~wt-port", decode_port),
+    ("~routers", decode_namehashes),
+    ("~api-spec", decode_api_spec),
+    ("~price", decode_price),
+    ("~usage-root", decode_json),
+];
+
+/// A node as returned to clients: the indexed node plus its decoded data keys.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NodeResponse {
+    #[serde(flatten)]
+    pub node: Node,
+    /// latest value of each data key, keyed by label
+    pub decoded: BTreeMap<String, DecodedData>,
+}
+
+impl From<Node> for NodeResponse {
+    fn from(node: Node) -> Self {
+        let decoded = node
+            .data_keys
+            .iter()
+            .filter_map(|(label, data_key)| {
+                let latest = match data_key {
+                    DataKey::Fact(fact) => fact,
+                    DataKey::Note(notes) => notes.last()?,
+                };
+                Some((label.clone(), decode(label, &latest.data)))
+            })
+            .collect();
+        NodeResponse { node, decoded }
+    }
+}
+
+/// Decode a data key by its label, falling back to content sniffing.
+pub fn decode(label: &str, data: &[u8]) -> DecodedData {
+    DECODERS
+        .iter()
+        .find(|(known, _)| *known == label)
+        .and_then(|(_, decoder)| decoder(data))
+        .unwrap_or_else(|| decode_fallback(data))
+}
+
+/// JSON if it parses, text if it's printable UTF-8, hex otherwise.
+fn decode_fallback(data: &[u8]) -> DecodedData {
+    decode_json(data)
+        .or_else(|| decode_text(data))
+        .unwrap_or_else(|| DecodedData::Hex(to_hex(data)))
+}
+
+/// Whether a label has a fixed meaning and encoding, as opposed to free-form `~` notes
+/// such as API specs.
+pub fn has_fixed_encoding(label: &str) -> bool {
+    label != "~api-spec" && DECODERS.iter().any(|(known, _)| *known == label)
+}
+
+fn decode_hex(data: &[u8]) -> Option<DecodedData> {
+    Some(DecodedData::Hex(to_hex(data)))
+}
+
+fn decode_text(data: &[u8]) -> Option<DecodedData> {
+    let text = std::str::from_utf8(data).ok()?;
+    if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return None;
+    }
+    Some(DecodedData::Text(text.to_string()))
+}
+
+fn decode_json(data: &[u8]) -> Option<DecodedData> {
+    serde_json::from_slice(data).ok().map(DecodedData::Json)
+}
+
+/// Specs may be written as JSON or YAML.
+fn decode_api_spec(data: &[u8]) -> Option<DecodedData> {
+    parse_spec(data).map(DecodedData::Json)
+}
+
+fn decode_ip(data: &[u8]) -> Option<DecodedData> {
+    let ip = match data.len() {
+        4 => Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?).to_string(),
+        16 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?);
+            // kinode writes IPv4 addresses as IPv4-mapped IPv6
+            match ip.to_ipv4_mapped() {
+                Some(v4) => v4.to_string(),
+                None => ip.to_string(),
+            }
+        }
+        _ => return None,
+    };
+    Some(DecodedData::Ip(ip))
+}
+
+fn decode_port(data: &[u8]) -> Option<DecodedData> {
+    let bytes = <[u8; 2]>::try_from(data).ok()?;
+    Some(DecodedData::Port(u16::from_be_bytes(bytes)))
+}
+
+fn decode_namehashes(data: &[u8]) -> Option<DecodedData> {
+    if data.is_empty() || data.len() % 32 != 0 {
+        return None;
+    }
+    Some(DecodedData::Namehashes(data.chunks(32).map(to_hex).collect()))
+}
+
+/// Prices are written either as text/JSON or as a raw uint256.
+fn decode_price(data: &[u8]) -> Option<DecodedData> {
+    if data.len() == 32 {
+        return Some(DecodedData::Number(U256::from_be_slice(data).to_string()));
+    }
+    decode_json(data).or_else(|| decode_text(data))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    format!(
+        "0x{}",
+        data.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+    )
+}
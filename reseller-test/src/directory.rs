@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::Address;
+use kinode_process_lib::kiprintln;
+
+use crate::contracts::{eth_call, ApiRegistry};
+use crate::structs::{
+    NameEntry,
+    Ownership,
+    ResellerState
+};
+
+/// List the resellers registered under the ApiRegistry.
+///
+/// # Example
+/// ```
+/// let request = ListResellers {
+///     refresh: true,
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ListResellers {
+    /// rebuild the directory from the index and the registry contract before listing
+    pub refresh: bool,
+}
+
+/// A reseller known from the index, the registry contract, or both.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ResellerEntry {
+    /// full kimap name of the reseller entry, or the label it registered under if it isn't indexed
+    pub name: String,
+    /// `None` if the registry lists a tba the index can't place
+    pub namehash: Option<String>,
+    pub tba: Address,
+    pub owner: Option<Address>,
+    /// Kinode node serving the reseller's API, as registered with the ApiRegistry
+    pub node_name: Option<String>,
+    /// `~` data keys on the reseller entry, e.g. `~api-spec` or `~price`
+    pub offerings: Vec<String>,
+    /// whether the ApiRegistry's `getResellerList` includes the reseller
+    pub registered: bool,
+    /// why the entry's ownership couldn't be resolved on the last refresh, leaving
+    /// `owner` (and for index-only entries, `tba`) as last indexed
+    pub error: Option<String>,
+}
+
+/// The reseller directory, rebuilding it first if asked to or if a log touched a reseller.
+pub fn list_resellers(state: &mut ResellerState, request: ListResellers) -> anyhow::Result<Vec<ResellerEntry>> {
+    if request.refresh || state.directory_stale {
+        return refresh_directory(state);
+    }
+    state.db.list_resellers()
+}
+
+/// Rebuild the directory from every indexed entry whose gene is `reseller_impl`,
+/// merged with what the ApiRegistry contract reports.
+///
+/// The registry is optional: if the call fails the gene-based entries are still stored.
+/// An entry whose ownership can't be resolved keeps what the index holds and records
+/// the error instead of failing the refresh.
+pub fn refresh_directory(state: &mut ResellerState) -> anyhow::Result<Vec<ResellerEntry>> {
+    let mut resellers: BTreeMap<Address, ResellerEntry> = BTreeMap::new();
+
+    if let Some(reseller_impl) = state.config.reseller_impl {
+        for entry in state.db.names_by_gene(reseller_impl)? {
+            let (ownership, error) = ownership(state, &entry.namehash)?;
+            let Some(tba) = ownership.tba else {
+                if let Some(e) = error {
+                    kiprintln!("reseller {} left out of the directory: {e}", entry.name);
+                }
+                continue;
+            };
+            resellers.insert(
+                tba,
+                ResellerEntry {
+                    offerings: offerings(state, &entry.namehash)?,
+                    name: entry.name,
+                    namehash: Some(entry.namehash),
+                    tba,
+                    owner: ownership.owner,
+                    node_name: None,
+                    registered: false,
+                    error,
+                },
+            );
+        }
+    }
+
+    match registered_resellers(state) {
+        Ok(registered) => {
+            for (name, data) in registered {
+                if let Some(reseller) = resellers.get_mut(&data.tba) {
+                    reseller.node_name = Some(data.node_name);
+                    reseller.registered = true;
+                    continue;
+                }
+                let entry = registered_entry(state, &name, data.tba)?;
+                let (owner, offerings, error) = match &entry {
+                    Some(entry) => {
+                        let (ownership, error) = ownership(state, &entry.namehash)?;
+                        (ownership.owner, offerings(state, &entry.namehash)?, error)
+                    }
+                    None => (None, vec![], None),
+                };
+                resellers.insert(
+                    data.tba,
+                    ResellerEntry {
+                        namehash: entry.as_ref().map(|entry| entry.namehash.clone()),
+                        name: entry.map_or(name, |entry| entry.name),
+                        tba: data.tba,
+                        owner,
+                        node_name: Some(data.node_name),
+                        offerings,
+                        registered: true,
+                        error,
+                    },
+                );
+            }
+        }
+        Err(e) => kiprintln!("reseller directory built from the index only: {e:?}"),
+    }
+
+    let resellers: Vec<ResellerEntry> = resellers.into_values().collect();
+    state.db.replace_resellers(&resellers)?;
+    state.directory_stale = false;
+    kiprintln!("reseller directory refreshed: {} resellers", resellers.len());
+    state.db.list_resellers()
+}
+
+/// Ownership of an entry, falling back to what the index holds if resolving it fails,
+/// along with the failure.
+fn ownership(state: &mut ResellerState, namehash: &str) -> anyhow::Result<(Ownership, Option<String>)> {
+    match state.resolve_ownership(namehash) {
+        Ok(ownership) => Ok((ownership, None)),
+        Err(e) => {
+            let indexed = state.db.get_ownership(namehash)?.unwrap_or_default();
+            Ok((indexed, Some(e.to_string())))
+        }
+    }
+}
+
+/// The indexed entry of a registered reseller. The registry lists labels, not full
+/// names, so the tba tells us which entry it is; until the tba is indexed, the label is
+/// looked up below the registry's own entry.
+fn registered_entry(state: &ResellerState, label: &str, tba: Address) -> anyhow::Result<Option<NameEntry>> {
+    if let Some(entry) = state.db.name_by_tba(tba)? {
+        return Ok(Some(entry));
+    }
+    let Some(registry) = state.config.api_registry_tba else {
+        return Ok(None);
+    };
+    let Some(parent) = state.db.name_by_tba(registry)? else {
+        return Ok(None);
+    };
+    let name = format!("{label}.{}", parent.name);
+    Ok(state.db.namehash_of(&name)?.map(|namehash| NameEntry { name, namehash }))
+}
+
+/// `(label, data)` for every reseller in the ApiRegistry's list.
+fn registered_resellers(state: &ResellerState) -> anyhow::Result<Vec<(String, ApiRegistry::ResellerData)>> {
+    let Some(registry) = state.config.api_registry_tba else {
+        return Ok(vec![]);
+    };
+    let provider = &state.kimap.provider;
+    let names = eth_call(provider, registry, ApiRegistry::getResellerListCall {})?._0;
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    let data = eth_call(
+        provider,
+        registry,
+        ApiRegistry::getResellersCall {
+            resellersToGet: names.clone(),
+        },
+    )?
+    ._0;
+    Ok(names.into_iter().zip(data).collect())
+}
+
+/// Labels of the `~` data keys on an entry.
+fn offerings(state: &ResellerState, namehash: &str) -> anyhow::Result<Vec<String>> {
+    Ok(state
+        .db
+        .get_node(namehash)?
+        .map(|node| {
+            node.data_keys
+                .into_keys()
+                .filter(|label| label.starts_with('~'))
+                .collect()
+        })
+        .unwrap_or_default())
+}
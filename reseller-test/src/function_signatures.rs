@@ -4,32 +4,141 @@ use serde::{
     Serialize
 };
 use process_macros::SerdeJsonInto;
+use alloy_primitives::Address;
 use kinode_process_lib::eth::{
-    EthSubResult,
-    EthSub
+    EthSub,
+    EthSubError
 };
 
+use crate::decoders::NodeResponse;
+use crate::snapshot::SnapshotInfo;
+use crate::audit::{
+    AuditIndex,
+    AuditReport
+};
+use crate::catalog::{
+    ListAllApis,
+    GetApiSpec,
+    ApiSpec
+};
+use crate::routing::ResellerStats;
+use crate::offering::{
+    Offering,
+    OfferingStatus,
+    PublishTransactions
+};
+use crate::contracts::UnsignedTransaction;
+use crate::token::{
+    GetTokenBalance,
+    TokenBalance,
+    TokenMetadata
+};
+use crate::vouchers::StoredVoucher;
+use crate::receipts::{
+    ListReceipts,
+    SignedReceipt
+};
+use crate::usage_roots::{
+    CommitUsage,
+    GetUsageProof,
+    IndexedUsageRoot,
+    ListUsageRoots,
+    UsageCommitment,
+    UsageProof
+};
+use crate::invoices::{
+    GenerateInvoice,
+    Invoice
+};
+use crate::onboarding::{
+    GetOnboardingStatus,
+    OnboardingStatus
+};
+use crate::contract_info::{
+    GetResellerContractInfo,
+    ResellerContractInfo
+};
+use crate::wallet::{
+    CreateWallet,
+    UnlockWallet,
+    TrackedTransaction,
+    WalletStatus
+};
+use crate::directory::{
+    ListResellers,
+    ResellerEntry
+};
 use crate::structs::{
-    Node,
     ResellerApiPacket,
-    //ResellerApiResponse,
+    ResellerApiResponse,
     RemoteApiResponse,
     RemoteApiRequest,
-    ApiKeyUpdate
+    ApiKeyUpdate,
+    ListAllAPIsResponse,
+    SyncStatus,
+    NameSearch,
+    NameEntry,
+    ListChildren,
+    ChildrenPage,
+    OwnedName,
+    NoteHistoryRequest,
+    NoteHistory,
+    SubtreeRequest,
+    SubtreeNode,
+    GetChangesSince,
+    ChangesSince
 };
 
 declare_types! {
     User {
         CallApi ResellerApiPacket => String
-        GetNode String => Node
+        GetNode String => NodeResponse
         GetTba String => String
         UpdateApiKey ApiKeyUpdate => String
+        GetSyncStatus () => SyncStatus
+        GetNodeByName String => NodeResponse
+        SearchNames NameSearch => Vec<NameEntry>
+        ListChildren ListChildren => ChildrenPage
+        GetNamesByOwner String => Vec<OwnedName>
+        GetNoteHistory NoteHistoryRequest => NoteHistory
+        GetSubtree SubtreeRequest => SubtreeNode
+        GetChangesSince GetChangesSince => ChangesSince
+        ExportSnapshot () => SnapshotInfo
+        AuditIndex AuditIndex => AuditReport
+        ListResellers ListResellers => Vec<ResellerEntry>
+        ListAllApis ListAllApis => ListAllAPIsResponse
+        GetApiSpec GetApiSpec => ApiSpec
+        GetRoutingStats () => Vec<ResellerStats>
+        SetOffering Offering => OfferingStatus
+        GetOffering () => OfferingStatus
+        PublishOffering () => PublishTransactions
+        CreateWallet CreateWallet => WalletStatus
+        UnlockWallet UnlockWallet => WalletStatus
+        LockWallet () => WalletStatus
+        GetWalletStatus () => WalletStatus
+        SendTransaction UnsignedTransaction => TrackedTransaction
+        GetResellerContractInfo GetResellerContractInfo => ResellerContractInfo
+        GetOnboardingStatus GetOnboardingStatus => OnboardingStatus
+        GetPaymentToken () => TokenMetadata
+        GetTokenBalance GetTokenBalance => TokenBalance
+        ListVouchers () => Vec<StoredVoucher>
+        ListReceipts ListReceipts => Vec<SignedReceipt>
+        VerifyReceipt SignedReceipt => bool
+        CommitUsage CommitUsage => UsageCommitment
+        GetUsageProof GetUsageProof => UsageProof
+        ListUsageRoots ListUsageRoots => Vec<IndexedUsageRoot>
+        GenerateInvoice GenerateInvoice => Invoice
     },
     Reseller {
         CallRemoteApi RemoteApiRequest => Result<RemoteApiResponse, String>
+        ServeApiCall ResellerApiPacket => Result<ResellerApiResponse, String>
+        GetUsageProof String => Result<UsageProof, String>
+        GetVoucher Address => Result<Option<StoredVoucher>, String>
     },
     Kinode {
-        Eth EthSub => Result<EthSubResult, String>
+        // `eth:distro:sys` sends an `EthSubResult`: `{"Ok": EthSub}` or `{"Err": EthSubError}`
+        Ok EthSub => Result<(), String>
+        Err EthSubError => Result<(), String>
         StateLog String => String
     }
 }
\ No newline at end of file
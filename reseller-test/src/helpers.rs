@@ -5,11 +5,14 @@ use crate::structs::{
 use std::collections::HashMap;
 use serde_json::json;
 
+/// Model Anthropic calls are served with when the packet doesn't name one.
+pub const ANTHROPIC_MODEL: &str = "claude-3-5-sonnet-20240620";
+
 /// Create a message for the anthropic api
 /// 
 /// # Example
 /// ```
-/// let message = create_anthropic_message("What is the meaning of life?".to_string());
+/// let message = create_anthropic_message("What is the meaning of life?".to_string(), ANTHROPIC_MODEL, &state);
 /// ```
 /// ## Output
 /// ```
@@ -19,7 +22,7 @@ use serde_json::json;
 ///     body: "".to_string(),
 ///     content: "What is the meaning of life?".to_string()
 /// };
-pub fn create_anthropic_message(content: String, state: &ResellerState) -> Result<AnthropicMessage, String> {
+pub fn create_anthropic_message(content: String, model: &str, state: &ResellerState) -> Result<AnthropicMessage, String> {
     let endpoint = "https://api.anthropic.com/v1/messages".to_string();
 
     let mut headers = HashMap::new();
@@ -34,7 +37,7 @@ pub fn create_anthropic_message(content: String, state: &ResellerState) -> Resul
     headers.insert("anthropic-version".to_string(), "2023-06-01".to_string());
 
     let body = json!({
-        "model": "claude-3-5-sonnet-20240620",
+        "model": model,
         "max_tokens": 1024,
         "temperature": 0.7,
         "messages": [
@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use alloy_primitives::{Address, U256};
 use serde::Serialize;
 use serde_json::Value;
 use url::Url;
@@ -15,7 +17,7 @@ use kinode_process_lib::{
         Method, 
         Response, 
         StatusCode
-    }, kiprintln
+    }, kiprintln, our
 };
 use crate::structs::{
     ResellerState,
@@ -26,17 +28,151 @@ use crate::structs::{
     RemoteApiProvider,
     RemoteApiMessage,
     ApiKeyUpdate,
+    ListAllAPIsResponse,
     //DataKey,
-    Node
+    Node,
+    SyncStatus,
+    NameSearch,
+    NameEntry,
+    ListChildren,
+    ChildrenPage,
+    OwnedName,
+    NoteHistoryRequest,
+    NoteHistory,
+    SubtreeRequest,
+    SubtreeNode,
+    GetChangesSince,
+    ChangesSince
 };
 use crate::helpers::create_anthropic_message;
+use crate::decoders::NodeResponse;
+use crate::snapshot::{
+    export_snapshot,
+    SnapshotInfo
+};
+use crate::audit::{
+    audit_index,
+    AuditReport
+};
+use crate::catalog::{
+    get_api_spec,
+    list_all_apis,
+    ApiSpec
+};
+use crate::payments::{
+    consume_payment,
+    quote,
+    verify_payment
+};
+use crate::receipts::{
+    issue_receipt,
+    list_receipts,
+    verify_receipt,
+    SignedReceipt
+};
+use crate::invoices::{
+    generate_invoice,
+    Invoice
+};
+use crate::usage_roots::{
+    commit_usage,
+    get_usage_proof,
+    list_usage_roots,
+    IndexedUsageRoot,
+    UsageCommitment,
+    UsageProof
+};
+use crate::vouchers::{
+    accept_voucher,
+    check_voucher,
+    list_vouchers,
+    StoredVoucher
+};
+use crate::routing::{
+    route_api_call,
+    ResellerStats
+};
+use crate::offering::{
+    offering_status,
+    publish_offering,
+    set_offering,
+    OfferingStatus,
+    PublishTransactions
+};
+use crate::wallet::{
+    create_wallet,
+    lock_wallet,
+    send_transaction,
+    unlock_wallet,
+    wallet_status,
+    TrackedTransaction,
+    WalletStatus
+};
+use crate::token::{
+    get_token_balance,
+    payment_token,
+    TokenBalance,
+    TokenMetadata
+};
+use crate::onboarding::{
+    get_onboarding_status,
+    OnboardingStatus
+};
+use crate::contract_info::{
+    get_reseller_contract_info,
+    ResellerContractInfo
+};
+use crate::directory::{
+    list_resellers,
+    ResellerEntry
+};
+
+/// Page size for searches and listings when the request doesn't set one.
+const DEFAULT_PAGE_SIZE: usize = 50;
+/// Upper bound on a single page, whatever the request asks for.
+const MAX_PAGE_SIZE: usize = 500;
+/// Deepest branch `GetSubtree` will render in one response.
+const MAX_SUBTREE_DEPTH: usize = 8;
+/// Most nodes `GetSubtree` will render in one response.
+const MAX_SUBTREE_NODES: usize = 1_000;
+/// Path bound with authentication; requests that spend money, hold keys or write
+/// files are only served here.
+pub const ADMIN_API_PATH: &str = "/admin-api";
 
 /// Add this enum near the top with your other types
 #[derive(Serialize)]
 #[serde(untagged)]
 enum HttpResponse {
     Json(ResellerApiResponse),
-    Node(Node),
+    Node(NodeResponse),
+    SyncStatus(SyncStatus),
+    Names(Vec<NameEntry>),
+    Children(ChildrenPage),
+    OwnedNames(Vec<OwnedName>),
+    NoteHistory(NoteHistory),
+    Subtree(SubtreeNode),
+    Changes(ChangesSince),
+    Snapshot(SnapshotInfo),
+    Audit(AuditReport),
+    Resellers(Vec<ResellerEntry>),
+    Apis(ListAllAPIsResponse),
+    ApiSpec(ApiSpec),
+    RoutingStats(Vec<ResellerStats>),
+    Offering(OfferingStatus),
+    Publish(PublishTransactions),
+    Wallet(WalletStatus),
+    Transaction(TrackedTransaction),
+    ContractInfo(ResellerContractInfo),
+    Onboarding(OnboardingStatus),
+    Token(TokenMetadata),
+    TokenBalance(TokenBalance),
+    Vouchers(Vec<StoredVoucher>),
+    Receipts(Vec<SignedReceipt>),
+    Verified(bool),
+    UsageCommitment(UsageCommitment),
+    UsageProof(UsageProof),
+    UsageRoots(Vec<IndexedUsageRoot>),
+    Invoice(Invoice),
     Text(String),
 }
 
@@ -49,14 +185,23 @@ pub fn http_handler(
     kiprintln!("HTTP request received at path: {:?}", path);
     kiprintln!("Request: {:#?}", request);
 
+    // `/api` is open to anyone who can reach the node; only logged-in users reach `/admin-api`
+    if requires_login(&request) && !path.ends_with(ADMIN_API_PATH) {
+        send_http_response(
+            StatusCode::UNAUTHORIZED,
+            HttpResponse::Text(format!("this request is only served at {ADMIN_API_PATH}")),
+        );
+        return;
+    }
+
     // Process the server request and prepare an appropriate response
     let (status, response) = match request {
-        UserRequest::CallApi(packet) => match process_api_call(state, packet) {
+        UserRequest::CallApi(packet) => match process_api_call(state, packet, &our().node) {
             Ok(resp) => (StatusCode::OK, HttpResponse::Json(resp)),
             Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e)),
         },
         UserRequest::GetNode(name_hash) => match get_node(state, name_hash) {
-            Ok(resp) => (StatusCode::OK, HttpResponse::Node(resp)),
+            Ok(resp) => (StatusCode::OK, HttpResponse::Node(resp.into())),
             Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e)),
         },
         UserRequest::GetTba(name_hash) => match get_tba(state, name_hash) {
@@ -67,24 +212,206 @@ pub fn http_handler(
             Ok(bytes) => (StatusCode::OK, HttpResponse::Text(String::from_utf8_lossy(&bytes).into_owned())),
             Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e)),
         },
+        UserRequest::GetSyncStatus(()) => (StatusCode::OK, HttpResponse::SyncStatus(state.sync.status())),
+        UserRequest::GetNodeByName(name) => match get_node_by_name(state, name) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Node(resp.into())),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e)),
+        },
+        UserRequest::SearchNames(search) => match search_names(state, search) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Names(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e)),
+        },
+        UserRequest::ListChildren(request) => match list_children(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Children(resp)),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e)),
+        },
+        UserRequest::GetNamesByOwner(address) => match get_names_by_owner(state, address) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::OwnedNames(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e)),
+        },
+        UserRequest::GetNoteHistory(request) => match get_note_history(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::NoteHistory(resp)),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e)),
+        },
+        UserRequest::GetSubtree(request) => match get_subtree(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Subtree(resp)),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e)),
+        },
+        UserRequest::GetChangesSince(GetChangesSince { block }) => match state.changes_since(block) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Changes(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::ExportSnapshot(()) => match export_snapshot(state) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Snapshot(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::AuditIndex(request) => match audit_index(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Audit(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::ListResellers(request) => match list_resellers(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Resellers(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::ListAllApis(request) => match list_all_apis(state, request) {
+            Ok(apis) => (StatusCode::OK, HttpResponse::Apis(ListAllAPIsResponse { apis })),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetApiSpec(request) => match get_api_spec(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::ApiSpec(resp)),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetRoutingStats(()) => (
+            StatusCode::OK,
+            HttpResponse::RoutingStats(state.reseller_stats.values().cloned().collect()),
+        ),
+        UserRequest::SetOffering(offering) => match set_offering(state, offering) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Offering(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e)),
+        },
+        UserRequest::GetOffering(()) => (StatusCode::OK, HttpResponse::Offering(offering_status(state))),
+        UserRequest::PublishOffering(()) => match publish_offering(state) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Publish(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e)),
+        },
+        UserRequest::CreateWallet(request) => match create_wallet(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Wallet(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::UnlockWallet(request) => match unlock_wallet(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Wallet(resp)),
+            Err(e) => (StatusCode::UNAUTHORIZED, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::LockWallet(()) => match lock_wallet(state) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Wallet(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetWalletStatus(()) => match wallet_status(state) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Wallet(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::SendTransaction(request) => match send_transaction(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Transaction(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetResellerContractInfo(request) => match get_reseller_contract_info(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::ContractInfo(resp)),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetOnboardingStatus(request) => match get_onboarding_status(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Onboarding(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetPaymentToken(()) => match payment_token(state) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Token(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetTokenBalance(request) => match get_token_balance(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::TokenBalance(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::ListVouchers(()) => match list_vouchers(state) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Vouchers(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::ListReceipts(request) => match list_receipts(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Receipts(resp)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::VerifyReceipt(receipt) => match verify_receipt(&receipt) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Verified(resp)),
+            Err(e) => (StatusCode::BAD_GATEWAY, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::CommitUsage(request) => match commit_usage(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::UsageCommitment(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GetUsageProof(request) => match get_usage_proof(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::UsageProof(resp)),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::ListUsageRoots(request) => match list_usage_roots(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::UsageRoots(resp)),
+            Err(e) => (StatusCode::NOT_FOUND, HttpResponse::Text(e.to_string())),
+        },
+        UserRequest::GenerateInvoice(request) => match generate_invoice(state, request) {
+            Ok(resp) => (StatusCode::OK, HttpResponse::Invoice(resp)),
+            Err(e) => (StatusCode::BAD_REQUEST, HttpResponse::Text(e.to_string())),
+        },
     };
 
     // Send the response to the client/user
     send_http_response(status, response);
 }
 
-/// Processes the API call from the client.
-fn process_api_call(
+/// Requests that spend our API key or funds, touch the hot wallet, rewrite the index,
+/// write files or read other customers' records.
+fn requires_login(request: &UserRequest) -> bool {
+    matches!(
+        request,
+        UserRequest::CallApi(_)
+            | UserRequest::UpdateApiKey(_)
+            | UserRequest::ExportSnapshot(_)
+            | UserRequest::AuditIndex(_)
+            | UserRequest::SetOffering(_)
+            | UserRequest::PublishOffering(_)
+            | UserRequest::CreateWallet(_)
+            | UserRequest::UnlockWallet(_)
+            | UserRequest::LockWallet(_)
+            | UserRequest::GetWalletStatus(_)
+            | UserRequest::SendTransaction(_)
+            | UserRequest::ListVouchers(_)
+            | UserRequest::ListReceipts(_)
+            | UserRequest::CommitUsage(_)
+            | UserRequest::GetUsageProof(_)
+            | UserRequest::GenerateInvoice(_)
+    )
+}
+
+/// Processes the API call from the client, served on behalf of the `customer` node.
+pub fn process_api_call(
     state: &mut ResellerState,
-    packet: ResellerApiPacket,
+    mut packet: ResellerApiPacket,
+    customer: &str,
 ) -> Result<ResellerApiResponse, String> {
+    if let Some(routing) = packet.routing.take() {
+        return route_api_call(state, packet, routing);
+    }
+    let model = packet.requested_model().to_string();
+    let mut payment = None;
+    let mut voucher = None;
+    let paid = if let Some(tx_hash) = packet.payment.take() {
+        payment = Some(verify_payment(state, tx_hash, customer, &model).map_err(|e| format!("Payment rejected: {e}"))?);
+        true
+    } else if let Some(signed) = packet.voucher.take() {
+        voucher = Some(check_voucher(state, signed, &model).map_err(|e| format!("Voucher rejected: {e}"))?);
+        true
+    } else {
+        false
+    };
+    let price = if paid { quote(state, &model).map_err(|e| e.to_string())? } else { U256::ZERO };
+    let message = packet.message.clone();
     let remote_response = call_remote_api(state, packet)?;
 
     if remote_response.content.is_empty() {
         return Err("Remote API returned empty content".to_string());
     }
+    // a payment or voucher is only charged for a call we actually served
+    if let Some(payment) = &payment {
+        consume_payment(state, payment).map_err(|e| format!("Payment rejected: {e}"))?;
+    }
+    if let Some(stored) = &voucher {
+        accept_voucher(state, stored).map_err(|e| format!("Voucher rejected: {e}"))?;
+    }
+    let response = remote_response.content[0].text.clone();
+    // a receipt we fail to sign shouldn't cost the customer the answer they paid for
+    let receipt = issue_receipt(state, customer, &message, &remote_response, &response, price)
+        .map_err(|e| kiprintln!("failed to issue receipt: {e}"))
+        .ok();
     Ok(ResellerApiResponse {
-        response: remote_response.content[0].text.clone(),
+        response,
+        served_by: None,
+        receipt,
     })
 }
 
@@ -93,30 +420,139 @@ fn get_node(
     state: &mut ResellerState,
     name_hash: String,
 ) -> Result<Node, String> {
-    let node = state
-        .index
-        .get(&name_hash)
-        .ok_or_else(|| format!("Node not found for hash: {}", name_hash))?;
+    state
+        .db
+        .get_node(&name_hash)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Node not found for hash: {}", name_hash))
+}
+
+/// looks up the namehash of a full name in the index
+fn namehash_of(
+    state: &ResellerState,
+    name: &str,
+) -> Result<String, String> {
+    state
+        .db
+        .namehash_of(name)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Name not found: {}", name))
+}
+
+/// gets the node from its full name, e.g. "zapi-registry1111.test-api-registry.os"
+fn get_node_by_name(
+    state: &mut ResellerState,
+    name: String,
+) -> Result<Node, String> {
+    let name_hash = namehash_of(state, &name)?;
+    get_node(state, name_hash)
+}
+
+/// searches the indexed names by prefix or substring, sorted by name
+fn search_names(
+    state: &mut ResellerState,
+    search: NameSearch,
+) -> Result<Vec<NameEntry>, String> {
+    let limit = search.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    state
+        .db
+        .search_names(&search.query, search.mode, limit)
+        .map_err(|e| e.to_string())
+}
+
+/// lists a page of a node's children, in name order
+fn list_children(
+    state: &mut ResellerState,
+    request: ListChildren,
+) -> Result<ChildrenPage, String> {
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let name_hash = namehash_of(state, &request.parent)?;
 
-    Ok(node.clone())
+    let total = state.db.child_count(&name_hash).map_err(|e| e.to_string())?;
+    let children = state
+        .db
+        .children(&name_hash, request.offset, limit)
+        .map_err(|e| e.to_string())?;
+
+    Ok(ChildrenPage {
+        parent: request.parent,
+        total,
+        offset: request.offset,
+        children,
+    })
 }
 
-/// gets the tba from the namehash in the state
+/// gets every indexed version of a note or fact on a named entry
+fn get_note_history(
+    state: &mut ResellerState,
+    request: NoteHistoryRequest,
+) -> Result<NoteHistory, String> {
+    let name_hash = namehash_of(state, &request.name)?;
+    let data_key = state
+        .db
+        .data_key(&name_hash, &request.label)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No data key {} on {}", request.label, request.name))?;
+
+    Ok(NoteHistory {
+        versions: data_key.versions().to_vec(),
+        name: request.name,
+        label: request.label,
+    })
+}
+
+/// gets a branch of the index as nested JSON, bounded to `MAX_SUBTREE_DEPTH` levels
+/// and `MAX_SUBTREE_NODES` nodes
+fn get_subtree(
+    state: &mut ResellerState,
+    request: SubtreeRequest,
+) -> Result<SubtreeNode, String> {
+    let name_hash = namehash_of(state, &request.root)?;
+    state
+        .subtree(&name_hash, request.depth.min(MAX_SUBTREE_DEPTH), request.include_data, MAX_SUBTREE_NODES)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Node not found for hash: {}", name_hash))
+}
+
+/// gets the tba, owner and data of a namehash from the index, as `kimap.get` reports them:
+/// a note or fact has no tba of its own, so it comes with its entry's tba and owner
 fn get_tba(
     state: &mut ResellerState,
     name_hash: String,
 ) -> Result<Vec<u8>, String> {
-    let Ok((tba, owner, data)) = state.kimap.get(&name_hash) else {
-        return Err("name not found".to_string());
+    let (entry_hash, data) = match state.db.data_key_by_hash(&name_hash).map_err(|e| e.to_string())? {
+        Some((entry_hash, label)) => {
+            let data = state
+                .db
+                .data_key(&entry_hash, &label)
+                .map_err(|e| e.to_string())?
+                .and_then(|data_key| data_key.versions().last().map(|version| version.data.clone()));
+            (entry_hash, data)
+        }
+        None => (name_hash, None),
     };
+    let ownership = state
+        .resolve_ownership(&entry_hash)
+        .map_err(|e| format!("name not found: {}", e))?;
     let info = serde_json::json!({
-        "tba": tba,
-        "owner": owner,
+        "tba": ownership.tba,
+        "owner": ownership.owner,
+        "gene": ownership.gene,
         "data": data,
     });
     serde_json::to_vec(&info).map_err(|e| format!("Serialization error: {}", e))
 }
 
+/// lists every indexed name currently held by an address
+fn get_names_by_owner(
+    state: &mut ResellerState,
+    address: String,
+) -> Result<Vec<OwnedName>, String> {
+    let owner = Address::from_str(&address)
+        .map_err(|e| format!("Invalid address {}: {}", address, e))?;
+    state.db.names_by_owner(owner).map_err(|e| e.to_string())
+}
+
 /// Builds and sends the remote API request, then extracts and deserializes the response body into a RemoteApiResponse.
 fn call_remote_api(
     state: &mut ResellerState,
@@ -166,7 +602,8 @@ fn build_remote_request(
 ) -> Result<RemoteApiRequest, String> {
     match packet.provider {
         RemoteApiProvider::Anthropic => {
-            let anth_msg = create_anthropic_message(packet.message, state)
+            let model = packet.requested_model().to_string();
+            let anth_msg = create_anthropic_message(packet.message, &model, state)
                 .map_err(|e| format!("Failed to create Anthropic message: {}", e))?;
             kiprintln!("Constructed Anthropic message: {:#?}", anth_msg);
             Ok(RemoteApiRequest {
@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::U256;
+use kinode_process_lib::{
+    our,
+    kiprintln,
+    vfs,
+    http::server::HttpServer
+};
+
+use crate::receipts::{
+    now,
+    ListReceipts
+};
+use crate::structs::ResellerState;
+use crate::token::{
+    format_units,
+    parse_units,
+    payment_token,
+    TokenMetadata
+};
+
+/// VFS drive invoice files are written to.
+const INVOICE_DRIVE: &str = "invoices";
+/// HTTP path invoice files are served under, below the process's own path.
+const INVOICE_HTTP_PATH: &str = "/invoices";
+/// Invoices bound again on start, the latest periods first; older ones are served
+/// again once regenerated.
+const MAX_SERVED_INVOICES: usize = 100;
+/// USD amounts are computed to this many decimals.
+const USD_DECIMALS: u8 = 6;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Build a customer's statement from the receipts we issued them.
+///
+/// # Example
+/// ```
+/// let request = GenerateInvoice {
+///     customer: "buyer.os".to_string(),
+///     from: 1735689600,
+///     to: 1738368000,
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct GenerateInvoice {
+    /// node the calls were served for
+    pub customer: String,
+    /// unix seconds, inclusive
+    pub from: u64,
+    /// unix seconds, exclusive
+    pub to: u64,
+}
+
+/// Usage of one model on one day.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct InvoiceLine {
+    /// UTC, as YYYY-MM-DD
+    pub day: String,
+    pub model: String,
+    pub calls: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// in base units of the payment token
+    pub amount: U256,
+    /// in whole tokens
+    pub formatted: String,
+    pub usd: Option<String>,
+}
+
+/// Where an invoice's files were written, and the HTTP paths they are served at.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct InvoiceFiles {
+    pub json: String,
+    pub csv: String,
+    pub json_url: String,
+    pub csv_url: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct Invoice {
+    pub id: String,
+    pub reseller: String,
+    pub customer: String,
+    pub from: u64,
+    pub to: u64,
+    /// unix seconds
+    pub issued: u64,
+    pub token: TokenMetadata,
+    /// USD value of one payment token, from the package config
+    pub usd_rate: Option<String>,
+    /// by day, then model
+    pub lines: Vec<InvoiceLine>,
+    pub calls: u64,
+    pub total: U256,
+    pub total_formatted: String,
+    pub total_usd: Option<String>,
+    pub files: InvoiceFiles,
+}
+
+/// Group the customer's receipts by day and model, price them, and write the invoice
+/// as JSON and CSV to VFS, served under `/invoices`.
+pub fn generate_invoice(state: &mut ResellerState, request: GenerateInvoice) -> anyhow::Result<Invoice> {
+    if request.from >= request.to {
+        return Err(anyhow::anyhow!("`from` must be before `to`"));
+    }
+    // the customer ends up in file and HTTP paths
+    if !is_node_name(&request.customer) {
+        return Err(anyhow::anyhow!("{:?} is not a node name", request.customer));
+    }
+    let token = payment_token(state)?;
+    let usd_rate = match &state.config.usd_rate {
+        Some(rate) => Some((rate.clone(), parse_units(rate, USD_DECIMALS)?)),
+        None => None,
+    };
+    // in USD base units, rounded down
+    let usd = |amount: U256| -> Option<U256> {
+        let (_, rate) = usd_rate.as_ref()?;
+        let scale = U256::from(10).pow(U256::from(token.decimals));
+        Some(amount.saturating_mul(*rate) / scale)
+    };
+
+    let receipts = state.db.receipts(&ListReceipts {
+        customer: Some(request.customer.clone()),
+        reseller: Some(our().node.clone()),
+        from: Some(request.from),
+        to: Some(request.to),
+    })?;
+    let mut lines: BTreeMap<(String, String), InvoiceLine> = BTreeMap::new();
+    for signed in &receipts {
+        let receipt = &signed.receipt;
+        let day = date(receipt.timestamp);
+        let line = lines
+            .entry((day.clone(), receipt.model.clone()))
+            .or_insert_with(|| InvoiceLine {
+                day,
+                model: receipt.model.clone(),
+                calls: 0,
+                input_tokens: 0,
+                output_tokens: 0,
+                amount: U256::ZERO,
+                formatted: String::new(),
+                usd: None,
+            });
+        line.calls += 1;
+        line.input_tokens += receipt.input_tokens as u64;
+        line.output_tokens += receipt.output_tokens as u64;
+        line.amount = line.amount.saturating_add(receipt.price);
+    }
+    // the USD total is the sum of the rounded lines, so the lines add up to it
+    let mut total_usd = usd_rate.as_ref().map(|_| U256::ZERO);
+    let lines: Vec<InvoiceLine> = lines
+        .into_values()
+        .map(|mut line| {
+            line.formatted = format_units(line.amount, token.decimals);
+            let line_usd = usd(line.amount);
+            total_usd = total_usd.zip(line_usd).map(|(total, line_usd)| total.saturating_add(line_usd));
+            line.usd = line_usd.map(|line_usd| format_units(line_usd, USD_DECIMALS));
+            line
+        })
+        .collect();
+    let total = lines.iter().fold(U256::ZERO, |total, line| total.saturating_add(line.amount));
+
+    let id = format!("{}_{}_{}", request.customer, request.from, request.to);
+    let invoice = Invoice {
+        files: invoice_files(&invoice_drive()?, &id),
+        id,
+        reseller: our().node.clone(),
+        customer: request.customer,
+        from: request.from,
+        to: request.to,
+        issued: now(),
+        usd_rate: usd_rate.as_ref().map(|(rate, _)| rate.clone()),
+        calls: receipts.len() as u64,
+        total_formatted: format_units(total, token.decimals),
+        total_usd: total_usd.map(|total_usd| format_units(total_usd, USD_DECIMALS)),
+        total,
+        lines,
+        token,
+    };
+    write_files(&invoice)?;
+    serve_invoice(&invoice.id, &invoice.files)?;
+    kiprintln!(
+        "invoice {} for {}: {} calls, {} {}",
+        invoice.id,
+        invoice.customer,
+        invoice.calls,
+        invoice.total_formatted,
+        invoice.token.symbol
+    );
+    Ok(invoice)
+}
+
+fn invoice_drive() -> anyhow::Result<String> {
+    vfs::create_drive(our().package_id(), INVOICE_DRIVE, None)
+        .map_err(|e| anyhow::anyhow!("failed to open invoice drive: {e:?}"))
+}
+
+fn invoice_files(drive: &str, id: &str) -> InvoiceFiles {
+    InvoiceFiles {
+        json: format!("{drive}/{id}.json"),
+        csv: format!("{drive}/{id}.csv"),
+        json_url: format!("/{}{INVOICE_HTTP_PATH}/{id}.json", our().process),
+        csv_url: format!("/{}{INVOICE_HTTP_PATH}/{id}.csv", our().process),
+    }
+}
+
+fn write_files(invoice: &Invoice) -> anyhow::Result<()> {
+    let files = &invoice.files;
+    for (path, bytes) in [(&files.json, serde_json::to_vec_pretty(invoice)?), (&files.csv, csv(invoice).into_bytes())] {
+        vfs::create_file(path, None)
+            .and_then(|file| file.write(&bytes))
+            .map_err(|e| anyhow::anyhow!("failed to write {path}: {e:?}"))?;
+    }
+    Ok(())
+}
+
+/// One row per line item, then a total row.
+fn csv(invoice: &Invoice) -> String {
+    let mut csv = String::from("day,model,calls,input_tokens,output_tokens,amount,currency,usd\n");
+    let symbol = &invoice.token.symbol;
+    for line in &invoice.lines {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            line.day,
+            csv_field(&line.model),
+            line.calls,
+            line.input_tokens,
+            line.output_tokens,
+            line.formatted,
+            csv_field(symbol),
+            line.usd.as_deref().unwrap_or_default(),
+        ));
+    }
+    csv.push_str(&format!(
+        "total,,{},{},{},{},{},{}\n",
+        invoice.calls,
+        invoice.lines.iter().map(|line| line.input_tokens).sum::<u64>(),
+        invoice.lines.iter().map(|line| line.output_tokens).sum::<u64>(),
+        invoice.total_formatted,
+        csv_field(symbol),
+        invoice.total_usd.as_deref().unwrap_or_default(),
+    ));
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Bind an invoice's files as static HTTP paths. Only logged-in users of the node can
+/// fetch them.
+fn serve_invoice(id: &str, files: &InvoiceFiles) -> anyhow::Result<()> {
+    let mut server = HttpServer::new(5);
+    for (path, extension, content_type) in [(&files.json, "json", "application/json"), (&files.csv, "csv", "text/csv")] {
+        let bytes = vfs::open_file(path, false, None)
+            .and_then(|file| file.read())
+            .map_err(|e| anyhow::anyhow!("failed to read {path}: {e:?}"))?;
+        server
+            .bind_http_static_path(
+                format!("{INVOICE_HTTP_PATH}/{id}.{extension}"),
+                true,
+                false,
+                Some(content_type.to_string()),
+                bytes,
+            )
+            .map_err(|e| anyhow::anyhow!("failed to serve {path}: {e:?}"))?;
+    }
+    Ok(())
+}
+
+/// Static bindings don't survive a restart; bind the invoices on the drive again, up
+/// to `MAX_SERVED_INVOICES` of them.
+pub fn serve_invoices() {
+    let listed = invoice_drive().and_then(|drive| Ok((invoice_ids(&drive)?, drive)));
+    let (ids, drive) = match listed {
+        Ok(listed) => listed,
+        Err(e) => {
+            kiprintln!("not serving invoices: {e}");
+            return;
+        }
+    };
+    for id in ids.iter().take(MAX_SERVED_INVOICES) {
+        if let Err(e) = serve_invoice(id, &invoice_files(&drive, id)) {
+            kiprintln!("not serving invoice {id}: {e}");
+        }
+    }
+    if ids.len() > MAX_SERVED_INVOICES {
+        kiprintln!(
+            "serving the latest {MAX_SERVED_INVOICES} of {} invoices; regenerate older ones to serve them",
+            ids.len()
+        );
+    }
+}
+
+/// Ids of the invoices written to the drive, latest period first.
+fn invoice_ids(drive: &str) -> anyhow::Result<Vec<String>> {
+    let entries = vfs::open_dir(drive, false, None)
+        .and_then(|dir| dir.read())
+        .map_err(|e| anyhow::anyhow!("failed to list {drive}: {e:?}"))?;
+    let mut ids: Vec<String> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let name = entry.path.rsplit('/').next()?;
+            name.strip_suffix(".json").map(str::to_string)
+        })
+        .collect();
+    ids.sort_by_key(|id| std::cmp::Reverse(period_end(id)));
+    Ok(ids)
+}
+
+/// The `to` of an invoice id, `<customer>_<from>_<to>`.
+fn period_end(id: &str) -> u64 {
+    id.rsplit('_').next().and_then(|to| to.parse().ok()).unwrap_or_default()
+}
+
+/// Lowercase letters, digits and hyphens, in dot-separated labels.
+fn is_node_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+/// The UTC date of a unix timestamp, as YYYY-MM-DD.
+fn date(timestamp: u64) -> String {
+    // days since 1970-01-01 to a civil date, after Howard Hinnant's `civil_from_days`
+    let days = (timestamp / SECONDS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_utc_days() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(SECONDS_PER_DAY - 1), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(1_709_164_800), "2024-02-29");
+        assert_eq!(date(1_735_689_599), "2024-12-31");
+        assert_eq!(date(1_735_689_600), "2025-01-01");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("claude-3-5-sonnet"), "claude-3-5-sonnet");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn invoice_ids_end_with_their_period() {
+        assert_eq!(period_end("buyer.os_1735689600_1738368000"), 1_738_368_000);
+        assert_eq!(period_end("not-an-invoice"), 0);
+    }
+
+    #[test]
+    fn only_node_names_are_customers() {
+        assert!(is_node_name("buyer.os"));
+        assert!(is_node_name("zapi-registry1111.test-api-registry.os"));
+        for name in ["", ".", "buyer..os", ".os", "buyer.", "../buyer.os", "Buyer.os", "a/b.os", "a b.os"] {
+            assert!(!is_node_name(name), "{name:?}");
+        }
+    }
+}
@@ -1,25 +1,109 @@
 use kinode_app_common::{erect, Binding, State};
 use kinode_process_lib::{
     kiprintln,
-    http::server::HttpBindingConfig,
+    http::server::{
+        HttpBindingConfig,
+        WsBindingConfig
+    },
 };
 
 mod function_signatures;
 
 mod http_handlers;
-use http_handlers::http_handler;
+use http_handlers::{http_handler, ADMIN_API_PATH};
 
 mod local_handlers;
-use local_handlers::local_handler;
+use local_handlers::{local_handler, schedule_heartbeat};
+
+mod remote_handlers;
+use remote_handlers::remote_handler;
+
+mod ws_handlers;
+use ws_handlers::{ws_handler, WS_PATH};
 
 mod structs;
 use structs::ResellerState;
 
 mod helpers;
 
-fn init_fn(_state: &mut ResellerState) {
+mod decoders;
+
+mod db;
+
+mod snapshot;
+
+mod audit;
+
+mod config;
+
+mod contracts;
+
+mod contract_info;
+
+mod directory;
+
+mod catalog;
+
+mod routing;
+
+mod offering;
+
+mod wallet;
+
+mod onboarding;
+
+mod token;
+
+mod payments;
+
+mod vouchers;
+
+mod receipts;
+
+mod usage_roots;
+
+mod invoices;
+
+fn init_fn(state: &mut ResellerState) {
     kiprintln!("Initializing reseller-test");
 
+    // pick up config changes made since the state was created
+    let config = config::PackageConfig::load();
+    if config != state.config {
+        state.apply_config(config);
+    }
+
+    if let Err(e) = state.db.open(&state.config.db_name()) {
+        panic!("failed to open kimap index database: {e:?}");
+    }
+
+    // A fresh node can start from a teammate's snapshot instead of the first kimap block.
+    if let Err(e) = snapshot::import_bootstrap_snapshot(state) {
+        kiprintln!("ignoring bootstrap snapshot: {e:?}");
+    }
+
+    if let Err(e) = catalog::reindex_api_specs(state) {
+        kiprintln!("api catalog not rebuilt: {e:?}");
+    }
+    if let Err(e) = usage_roots::reindex_usage_roots(state) {
+        kiprintln!("usage roots not rebuilt: {e:?}");
+    }
+
+    invoices::serve_invoices();
+
+    // Subscribe before catching up so no log falls between history and the subscription.
+    state.subscribe();
+
+    // Resumes from the persisted cursor if a previous run was interrupted.
+    if let Err(e) = state.sync_history() {
+        kiprintln!("history sync incomplete, will resume on the next log: {e:?}");
+    }
+
+    // no websocket client can be connected yet, and nobody wants history replayed as live updates
+    state.unpushed_changes.clear();
+
+    // a dropped subscription isn't always reported; renew it if it goes quiet
+    schedule_heartbeat();
 }
 
 erect! {
@@ -32,12 +116,20 @@ erect! {
             path: "/api",
             config: HttpBindingConfig::new(false, false, false, None),
         },
+        Binding::Http {
+            path: ADMIN_API_PATH,
+            config: HttpBindingConfig::new(true, false, false, None),
+        },
+        Binding::Ws {
+            path: WS_PATH,
+            config: WsBindingConfig::default(),
+        },
     ],
     handlers: {
         http: http_handler,
         local: local_handler,
-        remote: _,
-        ws: _,
+        remote: remote_handler,
+        ws: ws_handler,
     },
     init: init_fn,
     wit_world: "reseller-test-universal-dot-os-v1"
@@ -3,33 +3,42 @@ use kinode_process_lib::{
     http::server::HttpServer
 };
 use crate::ResellerState;
+use kinode_app_common::timer;
 use kinode_process_lib::{
     kiprintln,
     //set_state,
     eth::{
         EthSub, 
-        //EthSubResult, 
+        EthSubError,
+        EthSubResult, 
         SubscriptionResult
     }
 };
 use crate::function_signatures::KinodeRequest;
+use crate::receipts::now;
+use crate::structs::{ACCOUNT_SUBSCRIPTION_ID, KIMAP_SUBSCRIPTION_ID};
+use crate::ws_handlers::push_changes;
+use crate::wallet::poll_receipts;
+use crate::usage_roots::maybe_commit_usage;
 
 // Make note in future docs that this can be used to debug a process
 pub fn local_handler(
     message: &Message,
     state: &mut ResellerState,
-    _server: &mut HttpServer,
+    server: &mut HttpServer,
     request: KinodeRequest,
 ) {
     kiprintln!("kino_local state: {:?}", state);
     kiprintln!("kino_local message: {:#?}", message);
 
     match request {
-        KinodeRequest::Eth(eth_sub) => {
-            match handle_eth(state, eth_sub) {
-                Ok(()) => {kiprintln!("Successfully handled eth arm")}
-                Err(e) => kiprintln!("Error handling eth arm: {:?}", e)
-            }
+        KinodeRequest::Ok(eth_sub) => {
+            handle_eth_result(state, Ok(eth_sub));
+            push_changes(state, server);
+        }
+        KinodeRequest::Err(error) => {
+            handle_eth_result(state, Err(error));
+            push_changes(state, server);
         }
         KinodeRequest::StateLog(state_log) => {
             kiprintln!("Received state log: {:?}", state_log);
@@ -40,6 +49,43 @@ pub fn local_handler(
     }
 }
 
+/// How often the subscriptions are checked for a silent drop, and pending wallet work is done.
+const HEARTBEAT_INTERVAL_MS: u64 = 60_000;
+
+/// Check the subscriptions every `HEARTBEAT_INTERVAL_MS`, for as long as the process runs.
+/// Once the history sync is done, also poll wallet transactions and commit usage roots;
+/// neither needs to run per log.
+pub fn schedule_heartbeat() {
+    timer!(HEARTBEAT_INTERVAL_MS, (state: ResellerState) {
+        if let Err(e) = state.check_subscription() {
+            kiprintln!("Error renewing kimap subscription: {:?}", e);
+        }
+        if state.sync.live {
+            poll_receipts(state);
+            maybe_commit_usage(state);
+        }
+        schedule_heartbeat();
+    });
+}
+
+/// Handles a message from `eth:distro:sys` on one of our subscriptions.
+pub fn handle_eth_result(
+    state: &mut ResellerState,
+    result: EthSubResult,
+) {
+    state.last_eth_message = now();
+    match result {
+        Ok(eth_sub) => match handle_eth(state, eth_sub) {
+            Ok(()) => {kiprintln!("Successfully handled eth arm")}
+            Err(e) => kiprintln!("Error handling eth arm: {:?}", e)
+        },
+        Err(error) => match handle_eth_error(state, error) {
+            Ok(()) => {kiprintln!("Recovered kimap subscription")}
+            Err(e) => kiprintln!("Error recovering kimap subscription: {:?}", e)
+        },
+    }
+}
+
 pub fn handle_eth(
     state: &mut ResellerState,
     eth_sub: EthSub,
@@ -51,13 +97,47 @@ pub fn handle_eth(
     match sub_result {
         SubscriptionResult::Log(log) => {
             kiprintln!("Received eth log: {:?}", log);
+            if eth_sub.id != KIMAP_SUBSCRIPTION_ID && eth_sub.id != ACCOUNT_SUBSCRIPTION_ID {
+                kiprintln!("Ignoring log from unknown subscription {}", eth_sub.id);
+                return Ok(());
+            }
+            // the index has no undo for reorged logs; don't apply their removal notice twice
+            if log.removed {
+                kiprintln!("Ignoring removed (reorged) log: {:?}", log.transaction_hash);
+                return Ok(());
+            }
+            // account logs can arrive after their entry's mint, so they bypass the cursor
+            if eth_sub.id == ACCOUNT_SUBSCRIPTION_ID {
+                return state.apply_log(&log);
+            }
+            // fill any gap between the sync cursor and this log before applying it
+            if !state.sync.live {
+                state.sync_history()?;
+            }
             state.handle_log(&log).map_err(|e| anyhow::anyhow!("Log handling error: {}", e))?;
             //Ok(EthSubResult::Success) // Replace with however you define success.
             Ok(())
         },
         other => {
-            kiprintln!("Received unhandled eth subscription result: {:?}", other);
-            Err(anyhow::anyhow!("Unhandled subscription result: {:?}", other))
+            // only logs are requested by our filter; anything else is harmless noise
+            kiprintln!("Ignoring unexpected eth subscription result: {:?}", other);
+            Ok(())
         }
     }
+}
+
+/// Handles a subscription error from `eth:distro:sys`.
+///
+/// The provider drops the subscription when it errors, so we resubscribe and backfill
+/// the gap with `get_logs` from the sync cursor rather than waiting for logs that will
+/// never arrive.
+pub fn handle_eth_error(
+    state: &mut ResellerState,
+    error: EthSubError,
+) -> anyhow::Result<()> {
+    if error.id != KIMAP_SUBSCRIPTION_ID && error.id != ACCOUNT_SUBSCRIPTION_ID {
+        kiprintln!("Ignoring error for unknown subscription {}: {}", error.id, error.error);
+        return Ok(());
+    }
+    state.recover_subscription(&error.error)
 }
\ No newline at end of file
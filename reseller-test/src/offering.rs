@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::{
+    json,
+    Value
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{keccak256, B256};
+use kinode_process_lib::{
+    our,
+    kiprintln
+};
+
+use crate::catalog::ApiSummary;
+use crate::contracts::{
+    note_transaction,
+    Reseller,
+    UnsignedTransaction
+};
+use crate::structs::{
+    DataVersion,
+    ResellerState
+};
+
+/// What we sell: the models, prices, limits and endpoint our spec advertises.
+///
+/// # Example
+/// ```
+/// let offering = Offering {
+///     reseller: "zapi-registry1111.test-api-registry.os".to_string(),
+///     label: "~llm-proxy".to_string(),
+///     title: "LLM Proxy API".to_string(),
+///     version: "1.0.0".to_string(),
+///     description: Some("Anthropic models, billed per call".to_string()),
+///     provider: "anthropic".to_string(),
+///     currency: "USDC".to_string(),
+///     models: BTreeMap::from([(
+///         "claude-3-5-sonnet-20240620".to_string(),
+///         ModelPrice { per_call: Some("0.01".to_string()), input: None, output: None },
+///     )]),
+///     limits: OfferingLimits { requests_per_minute: Some(60), max_tokens: Some(1024) },
+/// };
+/// ```
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone, PartialEq)]
+pub struct Offering {
+    /// full name of our reseller entry, which the spec is written on
+    pub reseller: String,
+    /// note label of the spec; must start with `~`
+    pub label: String,
+    pub title: String,
+    pub version: String,
+    pub description: Option<String>,
+    /// e.g. "anthropic"
+    pub provider: String,
+    /// currency the prices are in, e.g. "USDC"
+    pub currency: String,
+    /// price of each model we serve, by model name
+    pub models: BTreeMap<String, ModelPrice>,
+    pub limits: OfferingLimits,
+}
+
+/// Prices are decimal strings so they survive the round trip through JSON exactly.
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone, PartialEq)]
+pub struct ModelPrice {
+    pub per_call: Option<String>,
+    /// per 1k input tokens
+    pub input: Option<String>,
+    /// per 1k output tokens
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone, PartialEq, Default)]
+pub struct OfferingLimits {
+    pub requests_per_minute: Option<u32>,
+    pub max_tokens: Option<u32>,
+}
+
+/// Where our offering's publication stands.
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
+pub enum PublicationStatus {
+    /// transactions were handed out; waiting for a matching `Note` log
+    Pending,
+    Published {
+        block: Option<u64>,
+        tx_hash: Option<B256>,
+    },
+}
+
+/// The spec we last asked the user to publish.
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
+pub struct Publication {
+    pub namehash: String,
+    pub label: String,
+    /// keccak256 of the spec bytes, matched against the data of `Note` logs
+    pub spec_hash: B256,
+    pub status: PublicationStatus,
+}
+
+/// Our offering and whether it is on chain.
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
+pub struct OfferingStatus {
+    pub offering: Option<Offering>,
+    pub publication: Option<Publication>,
+}
+
+/// The spec document and the transactions that publish it. Either transaction
+/// writes the same note; send one, from the reseller entry's owner.
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
+pub struct PublishTransactions {
+    pub spec: Value,
+    /// `addApiSpec(label, spec)` on the reseller tba
+    pub add_api_spec: UnsignedTransaction,
+    /// `execute(kimap, 0, note(label, spec), CALL)` on the reseller tba
+    pub execute: UnsignedTransaction,
+}
+
+impl Offering {
+    /// Payments and vouchers are quoted per call, so a node that requires them needs a
+    /// `per_call` price on every model.
+    fn validate(&self, require_payment: bool) -> Result<(), String> {
+        if !self.label.starts_with('~') {
+            return Err(format!("offering label {} must start with ~", self.label));
+        }
+        if self.models.is_empty() {
+            return Err("an offering needs at least one model".to_string());
+        }
+        for (model, price) in &self.models {
+            if price.per_call.is_none() && (price.input.is_none() || price.output.is_none()) {
+                return Err(format!("{model} needs a per_call price, or both input and output prices"));
+            }
+            if require_payment && price.per_call.is_none() {
+                return Err(format!("{model} needs a per_call price while require_payment is set"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The spec document, in the shape the API catalog parses.
+    pub fn spec(&self) -> Value {
+        let our = our();
+        json!({
+            "title": self.title,
+            "version": self.version,
+            "description": self.description,
+            "x-api-type": "REST",
+            "x-protocol": "kinode",
+            "provider": self.provider,
+            "models": self.models.keys().collect::<Vec<_>>(),
+            "pricing": {
+                "currency": self.currency,
+                "models": self.models,
+            },
+            "x-limits": self.limits,
+            "x-endpoint": {
+                "node": our.node,
+                "process": our.process.to_string(),
+            },
+        })
+    }
+}
+
+/// Store our offering, replacing any previous one. Does not publish it.
+pub fn set_offering(state: &mut ResellerState, offering: Offering) -> Result<OfferingStatus, String> {
+    offering.validate(state.config.require_payment)?;
+    let (_, errors) = ApiSummary::from_spec(&offering.spec());
+    if !errors.is_empty() {
+        return Err(format!("generated spec is invalid: {}", errors.join(", ")));
+    }
+    state.offering = Some(offering);
+    state.save();
+    Ok(offering_status(state))
+}
+
+pub fn offering_status(state: &ResellerState) -> OfferingStatus {
+    OfferingStatus {
+        offering: state.offering.clone(),
+        publication: state.publication.clone(),
+    }
+}
+
+/// Build the transactions that publish our offering, and start watching for the
+/// resulting `Note` log.
+pub fn publish_offering(state: &mut ResellerState) -> Result<PublishTransactions, String> {
+    let offering = state
+        .offering
+        .clone()
+        .ok_or("No offering set; send SetOffering first")?;
+    let (transactions, publication) = publish_transactions(state, &offering)?;
+    state.publication = Some(publication);
+    state.save();
+    Ok(transactions)
+}
+
+/// The transactions that would publish an offering, and the publication they would
+/// confirm. Nothing is recorded.
+pub fn publish_transactions(
+    state: &mut ResellerState,
+    offering: &Offering,
+) -> Result<(PublishTransactions, Publication), String> {
+    let namehash = state
+        .db
+        .namehash_of(&offering.reseller)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Reseller entry not found: {}", offering.reseller))?;
+    let tba = state
+        .resolve_ownership(&namehash)
+        .map_err(|e| e.to_string())?
+        .tba
+        .ok_or_else(|| format!("{} has no tba", offering.reseller))?;
+
+    let spec = offering.spec();
+    let spec_bytes = serde_json::to_vec(&spec).map_err(|e| e.to_string())?;
+    let chain_id = state.config.chain_id;
+
+    let add_api_spec = UnsignedTransaction::new(
+        chain_id,
+        tba,
+        Reseller::addApiSpecCall {
+            noteLabel: offering.label.clone(),
+            apiSpec: spec_bytes.clone().into(),
+        },
+        format!("addApiSpec({}) on {}", offering.label, offering.reseller),
+    );
+    let execute = note_transaction(
+        chain_id,
+        state.config.kimap_address,
+        tba,
+        &offering.label,
+        spec_bytes.clone(),
+        format!("note {} on {} through its tba", offering.label, offering.reseller),
+    );
+
+    let publication = Publication {
+        namehash,
+        label: offering.label.clone(),
+        spec_hash: keccak256(&spec_bytes),
+        status: PublicationStatus::Pending,
+    };
+    let transactions = PublishTransactions {
+        spec,
+        add_api_spec,
+        execute,
+    };
+    Ok((transactions, publication))
+}
+
+/// Mark a pending publication as published once its `Note` log is indexed.
+pub fn confirm_publication(state: &mut ResellerState, namehash: &str, label: &str, version: &DataVersion) {
+    let Some(publication) = state.publication.as_mut() else {
+        return;
+    };
+    if !matches!(publication.status, PublicationStatus::Pending)
+        || publication.namehash != namehash
+        || publication.label != label
+        || publication.spec_hash != keccak256(&version.data)
+    {
+        return;
+    }
+    publication.status = PublicationStatus::Published {
+        block: version.block,
+        tx_hash: version.tx_hash,
+    };
+    kiprintln!("offering {label} published in block {:?}", version.block);
+}
@@ -0,0 +1,249 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::Address;
+use kinode_process_lib::our;
+
+use crate::catalog::ApiCatalogEntry;
+use crate::contract_info::{
+    get_reseller_contract_info,
+    GetResellerContractInfo
+};
+use crate::contracts::{
+    eth_call,
+    ApiRegistry,
+    Reseller,
+    UnsignedTransaction
+};
+use crate::offering::{
+    publish_transactions,
+    PublicationStatus
+};
+use crate::structs::ResellerState;
+
+/// Check how far this node is from selling through the ApiRegistry.
+///
+/// # Example
+/// ```
+/// let request = GetOnboardingStatus {
+///     reseller: Some("zapi-registry1111.test-api-registry.os".to_string()),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct GetOnboardingStatus {
+    /// full name of our reseller entry; defaults to the offering's reseller, then to
+    /// the directory entry registered with our node name
+    pub reseller: Option<String>,
+}
+
+/// One item of the onboarding checklist.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct OnboardingStep {
+    /// stable identifier, e.g. "registered"
+    pub id: String,
+    pub description: String,
+    pub done: bool,
+    /// optional steps don't count towards `ready`
+    pub required: bool,
+    /// what was found, or what to do next
+    pub detail: Option<String>,
+    /// a transaction that completes the step, when one can be built
+    pub transaction: Option<UnsignedTransaction>,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct OnboardingStatus {
+    pub node: String,
+    pub reseller: Option<String>,
+    /// every required step is done
+    pub ready: bool,
+    pub steps: Vec<OnboardingStep>,
+    /// valid API specs on our reseller entry
+    pub live_apis: Vec<ApiCatalogEntry>,
+}
+
+impl OnboardingStep {
+    fn new(id: &str, description: &str, done: bool, detail: Option<String>) -> Self {
+        OnboardingStep {
+            id: id.to_string(),
+            description: description.to_string(),
+            done,
+            required: true,
+            detail,
+            transaction: None,
+        }
+    }
+
+    fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    fn with_transaction(mut self, transaction: Option<UnsignedTransaction>) -> Self {
+        if !self.done {
+            self.transaction = transaction;
+        }
+        self
+    }
+}
+
+/// Combine the kimap index, contract reads and local config into a checklist.
+pub fn get_onboarding_status(state: &mut ResellerState, request: GetOnboardingStatus) -> anyhow::Result<OnboardingStatus> {
+    let node = our().node.clone();
+    let reseller = match request.reseller {
+        Some(reseller) => Some(reseller),
+        None => match &state.offering {
+            Some(offering) => Some(offering.reseller.clone()),
+            None => state
+                .db
+                .list_resellers()?
+                .into_iter()
+                .find(|entry| entry.node_name.as_deref() == Some(node.as_str()) && entry.namehash.is_some())
+                .map(|entry| entry.name),
+        },
+    };
+    let mut steps = vec![];
+
+    let node_indexed = state.db.namehash_of(&node)?.is_some();
+    steps.push(OnboardingStep::new(
+        "node_entry",
+        "our node's name is in the kimap index",
+        node_indexed,
+        (!node_indexed).then(|| format!("{node} not found; is the index still syncing?")),
+    ));
+
+    let namehash = match &reseller {
+        Some(reseller) => state.db.namehash_of(reseller)?,
+        None => None,
+    };
+    let register = match (&reseller, &namehash) {
+        (Some(reseller), None) => register_transaction(state, reseller, &node),
+        _ => None,
+    };
+    steps.push(
+        OnboardingStep::new(
+            "reseller_entry",
+            "a reseller entry exists under the ApiRegistry",
+            namehash.is_some(),
+            match (&reseller, &namehash) {
+                (None, _) => Some("no reseller entry chosen; pass `reseller` with the name to register".to_string()),
+                (Some(reseller), None) => Some(format!("{reseller} is not minted yet")),
+                (Some(reseller), Some(_)) => Some(reseller.clone()),
+            },
+        )
+        .with_transaction(register),
+    );
+
+    let mut live_apis = vec![];
+    if let (Some(reseller), Some(namehash)) = (&reseller, &namehash) {
+        let info = get_reseller_contract_info(state, GetResellerContractInfo { name: reseller.clone() })?;
+
+        steps.push(OnboardingStep::new(
+            "reseller_impl",
+            "the entry's tba uses the reseller implementation",
+            info.is_reseller_impl,
+            Some(format!("gene {:?}, expected {:?}", info.gene, state.config.reseller_impl)),
+        ));
+
+        let registered = info
+            .registration
+            .as_ref()
+            .is_some_and(|registration| registration.matches && registration.node_name == node);
+        steps.push(OnboardingStep::new(
+            "registered",
+            "the ApiRegistry lists the entry with our node name",
+            registered,
+            info.registration
+                .as_ref()
+                .map(|registration| format!("registry has {} for {}", registration.tba, registration.node_name)),
+        ));
+
+        live_apis = state
+            .db
+            .api_specs()?
+            .into_iter()
+            .map(|spec| spec.entry)
+            .filter(|entry| entry.namehash == *namehash && entry.errors.is_empty())
+            .collect();
+
+        let (published, detail) = match &state.publication {
+            Some(publication) if publication.namehash == *namehash => match publication.status {
+                PublicationStatus::Published { block, .. } => (true, Some(format!("published in block {block:?}"))),
+                PublicationStatus::Pending => (false, Some("waiting for the publish transaction".to_string())),
+            },
+            _ => (!live_apis.is_empty(), None),
+        };
+        let publish = match state.offering.clone() {
+            Some(offering) if !published => publish_transactions(state, &offering)
+                .ok()
+                .map(|(transactions, _)| transactions.add_api_spec),
+            _ => None,
+        };
+        steps.push(
+            OnboardingStep::new(
+                "api_spec",
+                "our API spec is live on the entry",
+                published,
+                detail.or_else(|| state.offering.is_none().then(|| "no offering set; send SetOffering".to_string())),
+            )
+            .with_transaction(publish),
+        );
+
+        if let Some(wallet) = &state.wallet {
+            let operator = eth_call(&state.kimap.provider, info.tba, Reseller::isOperatorCall { signer: wallet.address })
+                .is_ok_and(|result| result._0);
+            steps.push(
+                OnboardingStep::new(
+                    "hot_wallet_operator",
+                    "the hot wallet is an operator of the reseller tba",
+                    operator,
+                    Some(format!("hot wallet {}", wallet.address)),
+                )
+                .optional(),
+            );
+        }
+    }
+
+    let providers: Vec<&String> = state.remote_api_keys.keys().collect();
+    steps.push(OnboardingStep::new(
+        "upstream_keys",
+        "an upstream API key is configured",
+        !providers.is_empty(),
+        Some(if providers.is_empty() {
+            "no keys; send UpdateApiKey".to_string()
+        } else {
+            format!("keys for {providers:?}")
+        }),
+    ));
+
+    Ok(OnboardingStatus {
+        ready: steps.iter().all(|step| step.done || !step.required),
+        node,
+        reseller,
+        steps,
+        live_apis,
+    })
+}
+
+/// `registerAsReseller(label, node)` on the ApiRegistry, which mints the entry with
+/// the reseller implementation and records our node name.
+fn register_transaction(state: &ResellerState, reseller: &str, node: &str) -> Option<UnsignedTransaction> {
+    let registry = state.config.api_registry_tba?;
+    let label = reseller.split('.').next()?.to_string();
+    // a label the registry already knows can't be registered again
+    let existing = eth_call(&state.kimap.provider, registry, ApiRegistry::resellersCall { _0: label.clone() }).ok()?;
+    if existing.tba != Address::ZERO {
+        return None;
+    }
+    Some(UnsignedTransaction::new(
+        state.config.chain_id,
+        registry,
+        ApiRegistry::registerAsResellerCall {
+            reseller_name: label.clone(),
+            full_node_name: node.to_string(),
+        },
+        format!("registerAsReseller({label}, {node})"),
+    ))
+}
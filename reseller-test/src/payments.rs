@@ -0,0 +1,172 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{Address, B256, U256};
+use alloy_sol_types::SolEvent;
+use kinode_process_lib::kiprintln;
+
+use crate::contracts::ERC20;
+use crate::token::{
+    format_units,
+    parse_units,
+    payment_token
+};
+use crate::structs::ResellerState;
+
+/// A verified payment, recorded so its transaction can't pay for a second call.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ConsumedPayment {
+    pub tx_hash: B256,
+    pub payer: Address,
+    /// base units of the payment token sent to our tba
+    pub amount: U256,
+    pub block: u64,
+}
+
+/// The price of one call to `model`, in base units of the payment token.
+pub fn quote(state: &mut ResellerState, model: &str) -> anyhow::Result<U256> {
+    let offering = state
+        .offering
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("no offering set, so there is no price to pay"))?;
+    let per_call = offering
+        .models
+        .get(model)
+        .and_then(|price| price.per_call.clone())
+        .ok_or_else(|| anyhow::anyhow!("{model} has no per_call price"))?;
+    let token = payment_token(state)?;
+    parse_units(&per_call, token.decimals)
+}
+
+/// The tba of our offering's reseller entry, which payments are made to.
+pub fn reseller_tba(state: &mut ResellerState) -> anyhow::Result<Address> {
+    let reseller = state
+        .offering
+        .as_ref()
+        .map(|offering| offering.reseller.clone())
+        .ok_or_else(|| anyhow::anyhow!("no offering set, so there is no tba to pay"))?;
+    let namehash = state
+        .db
+        .namehash_of(&reseller)?
+        .ok_or_else(|| anyhow::anyhow!("reseller entry {reseller} not found"))?;
+    state
+        .resolve_ownership(&namehash)?
+        .tba
+        .ok_or_else(|| anyhow::anyhow!("{reseller} has no tba"))
+}
+
+/// The addresses that may pay on behalf of the `customer` node: the tba and owner of
+/// its kimap entry.
+fn customer_addresses(state: &mut ResellerState, customer: &str) -> anyhow::Result<Vec<Address>> {
+    let namehash = state
+        .db
+        .namehash_of(customer)?
+        .ok_or_else(|| anyhow::anyhow!("{customer} has no kimap entry to pay from"))?;
+    let ownership = state.resolve_ownership(&namehash)?;
+    let addresses: Vec<Address> = [ownership.tba, ownership.owner]
+        .into_iter()
+        .flatten()
+        .filter(|address| *address != Address::ZERO)
+        .collect();
+    if addresses.is_empty() {
+        return Err(anyhow::anyhow!("{customer} has neither a tba nor an owner to pay from"));
+    }
+    Ok(addresses)
+}
+
+/// Check that `tx_hash` paid at least the quoted price to our reseller tba, from the
+/// `customer` node's tba or owner. The payment is only spent by `consume_payment`,
+/// once the call it paid for is served.
+///
+/// The transaction must have succeeded, be `payment_confirmations` blocks deep, and
+/// contain payment token `Transfer`s from one of the customer's addresses to our tba
+/// adding up to the quote.
+pub fn verify_payment(
+    state: &mut ResellerState,
+    tx_hash: B256,
+    customer: &str,
+    model: &str,
+) -> anyhow::Result<ConsumedPayment> {
+    if state.db.payment_consumed(&tx_hash)? {
+        return Err(anyhow::anyhow!("payment {tx_hash} was already used"));
+    }
+    let price = quote(state, model)?;
+    let token = payment_token(state)?;
+    let tba = reseller_tba(state)?;
+    let payers = customer_addresses(state, customer)?;
+
+    let provider = &state.kimap.provider;
+    let receipt = provider
+        .get_transaction_receipt(tx_hash)
+        .map_err(|e| anyhow::anyhow!("failed to fetch receipt of {tx_hash}: {e:?}"))?
+        .ok_or_else(|| anyhow::anyhow!("payment {tx_hash} is not mined yet"))?;
+    if !receipt.status() {
+        return Err(anyhow::anyhow!("payment {tx_hash} reverted"));
+    }
+    let block = receipt
+        .block_number
+        .ok_or_else(|| anyhow::anyhow!("payment {tx_hash} has no block number"))?;
+    let head = provider
+        .get_block_number()
+        .map_err(|e| anyhow::anyhow!("failed to fetch head block: {e:?}"))?;
+    let confirmations = head.saturating_sub(block) + 1;
+    if confirmations < state.config.payment_confirmations {
+        return Err(anyhow::anyhow!(
+            "payment {tx_hash} has {confirmations} of {} confirmations; retry shortly",
+            state.config.payment_confirmations
+        ));
+    }
+
+    // totalled per sender, so a transfer from someone else can't pay for the customer
+    let mut sent: Vec<(Address, U256)> = payers.iter().map(|payer| (*payer, U256::ZERO)).collect();
+    for log in receipt.inner.logs() {
+        if log.address() != token.address || log.topics().first() != Some(&ERC20::Transfer::SIGNATURE_HASH) {
+            continue;
+        }
+        let Ok(transfer) = ERC20::Transfer::decode_log_data(log.data(), true) else {
+            continue;
+        };
+        if transfer.to != tba {
+            continue;
+        }
+        if let Some((_, amount)) = sent.iter_mut().find(|(payer, _)| *payer == transfer.from) {
+            *amount = amount.saturating_add(transfer.value);
+        }
+    }
+    let (payer, amount) = sent
+        .into_iter()
+        .max_by_key(|(_, amount)| *amount)
+        .unwrap_or((Address::ZERO, U256::ZERO));
+    if amount < price {
+        return Err(anyhow::anyhow!(
+            "payment {tx_hash} sent {} {} from {customer}'s addresses to {tba}, but a call costs {}",
+            format_units(amount, token.decimals),
+            token.symbol,
+            format_units(price, token.decimals),
+        ));
+    }
+
+    Ok(ConsumedPayment {
+        tx_hash,
+        payer,
+        amount,
+        block,
+    })
+}
+
+/// Mark a verified payment spent, so its transaction can't pay for another call.
+pub fn consume_payment(state: &mut ResellerState, payment: &ConsumedPayment) -> anyhow::Result<()> {
+    // the primary key makes a replay fail here too
+    state.db.insert_consumed_payment(payment)?;
+    let token = payment_token(state)?;
+    kiprintln!(
+        "accepted payment {}: {} {} from {}",
+        payment.tx_hash,
+        format_units(payment.amount, token.decimals),
+        token.symbol,
+        payment.payer
+    );
+    Ok(())
+}
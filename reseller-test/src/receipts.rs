@@ -0,0 +1,170 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{hex, keccak256, Address, Bytes, B256, U256};
+use rand::RngCore;
+use kinode_process_lib::{
+    our,
+    kiprintln,
+    net,
+    Address as ProcessAddress
+};
+
+use crate::structs::{
+    RemoteApiResponse,
+    ResellerState
+};
+
+/// What a reseller charged for one call it served.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone, PartialEq)]
+pub struct UsageReceipt {
+    pub request_id: String,
+    /// node that served the call
+    pub reseller: String,
+    /// node the call was served for
+    pub customer: String,
+    pub model: String,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    /// in base units of `token`; zero for calls that weren't paid for
+    pub price: U256,
+    pub token: Option<Address>,
+    /// unix seconds
+    pub timestamp: u64,
+    /// keccak256 of the request message
+    pub request_hash: B256,
+    /// keccak256 of the response text
+    pub response_hash: B256,
+}
+
+/// A receipt signed with the serving node's networking key, through `net:distro:sys`.
+///
+/// # Example
+/// ```
+/// let signed = SignedReceipt {
+///     receipt,
+///     signature: Bytes::from(net::sign(receipt.signing_bytes()?)?),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct SignedReceipt {
+    #[serde(flatten)]
+    pub receipt: UsageReceipt,
+    pub signature: Bytes,
+}
+
+/// Filter the stored receipts.
+///
+/// # Example
+/// ```
+/// let request = ListReceipts {
+///     customer: Some("buyer.os".to_string()),
+///     reseller: None,
+///     from: Some(1735689600),
+///     to: None,
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone, Default)]
+pub struct ListReceipts {
+    pub customer: Option<String>,
+    pub reseller: Option<String>,
+    /// unix seconds, inclusive
+    pub from: Option<u64>,
+    /// unix seconds, exclusive
+    pub to: Option<u64>,
+}
+
+impl UsageReceipt {
+    /// The bytes the signature is over: the receipt as JSON, fields in declaration order.
+    pub fn signing_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Sign and keep the receipt for a call we served.
+pub fn issue_receipt(
+    state: &mut ResellerState,
+    customer: &str,
+    message: &str,
+    response: &RemoteApiResponse,
+    text: &str,
+    price: U256,
+) -> anyhow::Result<SignedReceipt> {
+    let mut request_id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut request_id);
+    let receipt = UsageReceipt {
+        request_id: hex::encode(request_id),
+        reseller: our().node.clone(),
+        customer: customer.to_string(),
+        model: response.model.clone(),
+        input_tokens: response.usage.input_tokens,
+        output_tokens: response.usage.output_tokens,
+        price,
+        token: state.config.payment_token,
+        timestamp: now(),
+        request_hash: keccak256(message.as_bytes()),
+        response_hash: keccak256(text.as_bytes()),
+    };
+    let signature = net::sign(receipt.signing_bytes()?)
+        .map_err(|e| anyhow::anyhow!("failed to sign receipt: {e:?}"))?;
+    let signed = SignedReceipt {
+        receipt,
+        signature: Bytes::from(signature),
+    };
+    state.db.insert_receipt(&signed)?;
+    Ok(signed)
+}
+
+/// Whether the reseller named in the receipt signed it. Resellers run this process
+/// under the same id, so that is the address the signature is checked against.
+pub fn verify_receipt(signed: &SignedReceipt) -> anyhow::Result<bool> {
+    let from = ProcessAddress::new(&signed.receipt.reseller, our().process.clone());
+    net::verify(from, signed.receipt.signing_bytes()?, signed.signature.to_vec())
+        .map_err(|e| anyhow::anyhow!("failed to verify receipt: {e:?}"))
+}
+
+/// Check a receipt a reseller returned for our call, and keep it if it holds up: it
+/// must be signed by the node we called, name us, and hash our request and its response.
+pub fn record_receipt(
+    state: &mut ResellerState,
+    node_name: &str,
+    message: &str,
+    text: &str,
+    signed: &SignedReceipt,
+) -> anyhow::Result<()> {
+    let receipt = &signed.receipt;
+    if receipt.reseller != node_name {
+        return Err(anyhow::anyhow!("receipt is from {}, but {node_name} served the call", receipt.reseller));
+    }
+    if receipt.customer != our().node {
+        return Err(anyhow::anyhow!("receipt is made out to {}", receipt.customer));
+    }
+    if receipt.request_hash != keccak256(message.as_bytes()) || receipt.response_hash != keccak256(text.as_bytes()) {
+        return Err(anyhow::anyhow!("receipt hashes don't match the call"));
+    }
+    if !verify_receipt(signed)? {
+        return Err(anyhow::anyhow!("receipt signature is not {node_name}'s"));
+    }
+    state.db.insert_receipt(signed)?;
+    kiprintln!(
+        "receipt {} from {node_name}: {} in, {} out, price {}",
+        receipt.request_id,
+        receipt.input_tokens,
+        receipt.output_tokens,
+        receipt.price
+    );
+    Ok(())
+}
+
+pub fn list_receipts(state: &ResellerState, request: ListReceipts) -> anyhow::Result<Vec<SignedReceipt>> {
+    state.db.receipts(&request)
+}
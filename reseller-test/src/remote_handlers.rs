@@ -0,0 +1,77 @@
+use serde::Serialize;
+use kinode_process_lib::{
+    kiprintln,
+    Message,
+    Response
+};
+use crate::ResellerState;
+use crate::function_signatures::ResellerRequest;
+use crate::http_handlers::process_api_call;
+use crate::usage_roots::usage_proof;
+
+/// Handles requests from other nodes' reseller-test processes.
+pub fn remote_handler(
+    message: &Message,
+    state: &mut ResellerState,
+    request: ResellerRequest,
+) {
+    kiprintln!("Remote request from {}: {:?}", message.source(), request);
+
+    match request {
+        ResellerRequest::ServeApiCall(mut packet) => {
+            // a buyer routed this call to us; never route it onwards
+            packet.routing = None;
+            let unpaid = packet.payment.is_none() && packet.voucher.is_none();
+            let result = if state.config.require_payment && unpaid {
+                Err("This reseller requires a payment transaction or voucher with every call".to_string())
+            } else if let Err(e) = check_offered(state, packet.requested_model()) {
+                Err(e)
+            } else {
+                process_api_call(state, packet, &message.source().node)
+            };
+            if let Err(e) = &result {
+                kiprintln!("Failed to serve call for {}: {e}", message.source().node);
+            }
+            respond(message, &result);
+        }
+        // a buyer that lost track of a call it paid for catches up with what we charged
+        ResellerRequest::GetVoucher(payer) => {
+            let result = state.db.voucher(&payer).map_err(|e| e.to_string());
+            respond(message, &result);
+        }
+        ResellerRequest::GetUsageProof(request_id) => {
+            let result = usage_proof(state, &request_id, Some(&message.source().node)).map_err(|e| e.to_string());
+            respond(message, &result);
+        }
+        _ => {
+            kiprintln!("Received unhandled reseller request: {:?}", request);
+        }
+    }
+}
+
+/// Answer the node that sent `message`; a failure is only logged, as there is no one
+/// left to tell.
+fn respond<T: Serialize>(message: &Message, result: &T) {
+    let body = match serde_json::to_vec(result) {
+        Ok(body) => body,
+        Err(e) => {
+            kiprintln!("Failed to serialize response to {}: {e}", message.source().node);
+            return;
+        }
+    };
+    if let Err(e) = Response::new().body(body).send() {
+        kiprintln!("Failed to respond to {}: {e:?}", message.source().node);
+    }
+}
+
+/// Other nodes may only call the models our offering advertises.
+fn check_offered(state: &ResellerState, model: &str) -> Result<(), String> {
+    let offering = state
+        .offering
+        .as_ref()
+        .ok_or_else(|| "This reseller has no offering, so it serves no models".to_string())?;
+    if !offering.models.contains_key(model) {
+        return Err(format!("This reseller doesn't offer {model}"));
+    }
+    Ok(())
+}
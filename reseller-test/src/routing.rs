@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::Value;
+use process_macros::SerdeJsonInto;
+use kinode_process_lib::{
+    our,
+    kiprintln,
+    Address,
+    Request
+};
+
+use crate::catalog::{
+    list_all_apis,
+    ApiCatalogEntry,
+    ListAllApis
+};
+use crate::function_signatures::ResellerRequest;
+use crate::receipts::record_receipt;
+use crate::token::{
+    parse_units,
+    payment_token
+};
+use crate::vouchers::{
+    commit_voucher,
+    sign_voucher,
+    sync_voucher_total,
+    SignedVoucher,
+    StoredVoucher
+};
+use crate::structs::{
+    RemoteApiProvider,
+    ResellerApiPacket,
+    ResellerApiResponse,
+    ResellerState
+};
+
+/// Seconds to wait for a reseller before falling back to the next one.
+const ROUTED_CALL_TIMEOUT: u64 = 30;
+/// Resellers tried for one request before giving up.
+const MAX_ROUTING_ATTEMPTS: usize = 3;
+/// Weight of the newest call in the latency and error-rate averages.
+const STATS_SMOOTHING: f64 = 0.2;
+
+/// Let the process pick the reseller for a `CallApi` request.
+///
+/// # Example
+/// ```
+/// let routing = Routing {
+///     model: "claude-3-5-sonnet-20240620".to_string(),
+///     strategy: RoutingStrategy::Best,
+///     vouchers: true,
+/// };
+/// ```
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
+pub struct Routing {
+    /// model the reseller must advertise in its API spec
+    pub model: String,
+    pub strategy: RoutingStrategy,
+    /// pay each call with a voucher signed by the hot wallet; resellers without a
+    /// `per_call` price are skipped
+    #[serde(default)]
+    pub vouchers: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone, Copy, PartialEq)]
+pub enum RoutingStrategy {
+    /// lowest advertised price first, per-call offers before per-token ones;
+    /// reliability and latency only break ties
+    Cheapest,
+    /// price weighted by the reseller's recent error rate and latency, per-call
+    /// offers before per-token ones
+    Best,
+}
+
+/// What this node has measured about a reseller it routed calls to.
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone, Default)]
+pub struct ResellerStats {
+    pub reseller: String,
+    pub calls: u64,
+    pub failures: u64,
+    /// moving average over recent calls, in milliseconds
+    pub latency_ms: f64,
+    /// moving average over recent calls, from 0 to 1
+    pub error_rate: f64,
+    pub last_error: Option<String>,
+}
+
+impl ResellerStats {
+    fn record(&mut self, latency_ms: f64, error: Option<String>) {
+        let failed = if error.is_some() { 1.0 } else { 0.0 };
+        if self.calls == 0 {
+            self.latency_ms = latency_ms;
+            self.error_rate = failed;
+        } else {
+            self.latency_ms += STATS_SMOOTHING * (latency_ms - self.latency_ms);
+            self.error_rate += STATS_SMOOTHING * (failed - self.error_rate);
+        }
+        self.calls += 1;
+        if error.is_some() {
+            self.failures += 1;
+            self.last_error = error;
+        }
+    }
+}
+
+/// A reseller able to serve a routed request.
+struct Candidate {
+    reseller: String,
+    node_name: String,
+    price: Option<Price>,
+    /// the advertised `per_call` price as published, for signing exact vouchers
+    per_call: Option<String>,
+}
+
+/// An advertised price. Per-call and per-token prices are in different units, so offers
+/// are only compared within a unit; per-call offers rank first since their cost is
+/// known before the call.
+#[derive(Debug, Clone, Copy)]
+enum Price {
+    PerCall(f64),
+    /// the sum of the `input` and `output` prices
+    PerToken(f64),
+}
+
+impl Price {
+    fn amount(self) -> f64 {
+        match self {
+            Price::PerCall(amount) | Price::PerToken(amount) => amount,
+        }
+    }
+
+    /// Rank of the price's unit, then the amount within it.
+    fn key(self) -> (u8, f64) {
+        match self {
+            Price::PerCall(amount) => (0, amount),
+            Price::PerToken(amount) => (1, amount),
+        }
+    }
+}
+
+/// Route a call to the reseller the strategy ranks first, falling back to the next
+/// candidate whenever one fails. The response names the reseller that served it.
+pub fn route_api_call(
+    state: &mut ResellerState,
+    packet: ResellerApiPacket,
+    routing: Routing,
+) -> Result<ResellerApiResponse, String> {
+    let candidates = rank_candidates(state, &packet.provider, &routing)?;
+    if candidates.is_empty() {
+        return Err(format!("No reseller with a node name offers {}", routing.model));
+    }
+
+    let mut errors = vec![];
+    for candidate in candidates.into_iter().take(MAX_ROUTING_ATTEMPTS) {
+        let mut packet = packet.clone();
+        packet.model = Some(routing.model.clone());
+        if routing.vouchers {
+            match candidate_voucher(state, &candidate) {
+                Ok(voucher) => packet.voucher = Some(voucher),
+                Err(e) => {
+                    errors.push(format!("{}: {e}", candidate.reseller));
+                    continue;
+                }
+            }
+        }
+        let started = std::time::Instant::now();
+        let result = call_reseller(&candidate.node_name, &packet);
+        let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        let stats = state
+            .reseller_stats
+            .entry(candidate.reseller.clone())
+            .or_insert_with(|| ResellerStats {
+                reseller: candidate.reseller.clone(),
+                ..Default::default()
+            });
+        stats.record(latency_ms, result.as_ref().err().cloned());
+        state.save();
+
+        match result {
+            Ok(mut response) => {
+                if let Some(voucher) = &packet.voucher {
+                    commit_voucher(state, voucher);
+                }
+                match &response.receipt {
+                    Some(receipt) => {
+                        if let Err(e) = record_receipt(state, &candidate.node_name, &packet.message, &response.response, receipt) {
+                            kiprintln!("discarding receipt from {}: {e}", candidate.reseller);
+                        }
+                    }
+                    None => kiprintln!("{} returned no receipt", candidate.reseller),
+                }
+                kiprintln!(
+                    "routed {} call to {} ({}) in {latency_ms:.0}ms",
+                    routing.model,
+                    candidate.reseller,
+                    candidate.node_name
+                );
+                response.served_by = Some(candidate.reseller);
+                return Ok(response);
+            }
+            Err(e) => {
+                // a timed-out reseller may still have served and charged the voucher
+                if let Some(voucher) = &packet.voucher {
+                    state.unsynced_vouchers.insert(voucher.payee);
+                }
+                kiprintln!("reseller {} failed, trying the next one: {e}", candidate.reseller);
+                errors.push(format!("{}: {e}", candidate.reseller));
+            }
+        }
+    }
+    Err(format!("Every reseller failed: {}", errors.join("; ")))
+}
+
+/// Resellers advertising the model for this provider, best first.
+fn rank_candidates(
+    state: &ResellerState,
+    provider: &RemoteApiProvider,
+    routing: &Routing,
+) -> Result<Vec<Candidate>, String> {
+    let apis = list_all_apis(
+        state,
+        ListAllApis {
+            provider: Some(provider_name(provider).to_string()),
+            model: Some(routing.model.clone()),
+        },
+    )
+    .map_err(|e| e.to_string())?;
+    let node_names: HashMap<String, String> = state
+        .db
+        .list_resellers()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter_map(|reseller| Some((reseller.name, reseller.node_name?)))
+        .collect();
+
+    let mut candidates: Vec<(f64, Candidate)> = vec![];
+    for api in apis {
+        let Some(node_name) = node_names.get(&api.reseller) else {
+            continue;
+        };
+        // calling ourselves would block on our own handler until the call times out
+        if *node_name == our().node {
+            continue;
+        }
+        // one candidate per reseller, at its cheapest matching offer
+        let price = advertised_price(&api, &routing.model);
+        let per_call = advertised_per_call(&api, &routing.model);
+        if let Some((_, existing)) = candidates.iter_mut().find(|(_, c)| c.reseller == api.reseller) {
+            if let Some(price) = price {
+                if !existing.price.is_some_and(|current| cmp_keys(current.key(), price.key()).is_le()) {
+                    existing.price = Some(price);
+                    existing.per_call = per_call;
+                }
+            }
+            continue;
+        }
+        candidates.push((
+            0.0,
+            Candidate {
+                reseller: api.reseller,
+                node_name: node_name.clone(),
+                price,
+                per_call,
+            },
+        ));
+    }
+
+    for (score, candidate) in candidates.iter_mut() {
+        *score = reliability_score(candidate, state.reseller_stats.get(&candidate.reseller));
+    }
+    match routing.strategy {
+        // reliability only orders resellers asking the same price
+        RoutingStrategy::Cheapest => candidates.sort_by(|(a_score, a), (b_score, b)| {
+            cmp_keys(price_key(a), price_key(b)).then(a_score.total_cmp(b_score))
+        }),
+        // scores are only comparable between prices in the same unit
+        RoutingStrategy::Best => candidates.sort_by(|(a_score, a), (b_score, b)| {
+            price_key(a).0.cmp(&price_key(b).0).then(a_score.total_cmp(b_score))
+        }),
+    }
+    Ok(candidates.into_iter().map(|(_, candidate)| candidate).collect())
+}
+
+/// Resellers without a price sort after every priced one.
+fn price_key(candidate: &Candidate) -> (u8, f64) {
+    candidate.price.map_or((2, f64::INFINITY), Price::key)
+}
+
+fn cmp_keys(a: (u8, f64), b: (u8, f64)) -> std::cmp::Ordering {
+    a.0.cmp(&b.0).then(a.1.total_cmp(&b.1))
+}
+
+/// Expected price per successful call, stretched by how slow the reseller is; lower is
+/// better. Resellers this node has never called start with a clean record.
+fn reliability_score(candidate: &Candidate, stats: Option<&ResellerStats>) -> f64 {
+    let (error_rate, latency_ms) = stats.map_or((0.0, 0.0), |stats| (stats.error_rate, stats.latency_ms));
+    // unpriced resellers still compete with each other on reliability
+    let price = candidate.price.map_or(1e12, Price::amount);
+    price / (1.0 - error_rate).max(0.05) * (1.0 + latency_ms / 1000.0)
+}
+
+/// The advertised price of a model. Pricing is read from `pricing.models.<model>`,
+/// `pricing.<model>` or `pricing` itself, as either a `per_call` price or the sum of
+/// `input` and `output` prices.
+fn advertised_price(api: &ApiCatalogEntry, model: &str) -> Option<Price> {
+    let price = model_pricing(api, model)?;
+    let number = |key: &str| -> Option<f64> {
+        match price.get(key)? {
+            Value::Number(number) => number.as_f64(),
+            Value::String(text) => text.parse().ok(),
+            _ => None,
+        }
+    };
+    number("per_call")
+        .map(Price::PerCall)
+        .or_else(|| Some(Price::PerToken(number("input")? + number("output")?)))
+}
+
+/// The advertised `per_call` price exactly as published.
+fn advertised_per_call(api: &ApiCatalogEntry, model: &str) -> Option<String> {
+    match model_pricing(api, model)?.get("per_call")? {
+        Value::Number(number) => Some(number.to_string()),
+        Value::String(text) => Some(text.clone()),
+        _ => None,
+    }
+}
+
+fn model_pricing<'a>(api: &'a ApiCatalogEntry, model: &str) -> Option<&'a Value> {
+    let pricing = api.summary.pricing.as_ref()?;
+    Some(
+        pricing
+            .get("models")
+            .and_then(|models| models.get(model))
+            .or_else(|| pricing.get(model))
+            .unwrap_or(pricing),
+    )
+}
+
+/// A voucher paying the candidate's tba its `per_call` price, in our payment token.
+fn candidate_voucher(state: &mut ResellerState, candidate: &Candidate) -> anyhow::Result<SignedVoucher> {
+    let per_call = candidate
+        .per_call
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("no per_call price to sign a voucher for"))?;
+    let price = parse_units(per_call, payment_token(state)?.decimals)?;
+    let namehash = state
+        .db
+        .namehash_of(&candidate.reseller)?
+        .ok_or_else(|| anyhow::anyhow!("reseller entry {} not found", candidate.reseller))?;
+    let tba = state
+        .resolve_ownership(&namehash)?
+        .tba
+        .ok_or_else(|| anyhow::anyhow!("{} has no tba", candidate.reseller))?;
+    if state.unsynced_vouchers.contains(&tba) {
+        let payer = state
+            .wallet
+            .as_ref()
+            .map(|wallet| wallet.address)
+            .ok_or_else(|| anyhow::anyhow!("no hot wallet; vouchers are signed with it"))?;
+        let stored = fetch_voucher(&candidate.node_name, payer).map_err(|e| anyhow::anyhow!(e))?;
+        sync_voucher_total(state, tba, stored)?;
+        state.unsynced_vouchers.remove(&tba);
+    }
+    sign_voucher(state, tba, price)
+}
+
+fn provider_name(provider: &RemoteApiProvider) -> &'static str {
+    match provider {
+        RemoteApiProvider::Anthropic => "anthropic",
+        RemoteApiProvider::OpenAi => "openai",
+    }
+}
+
+/// The latest voucher a reseller holds from `payer`, and what it charged against it.
+fn fetch_voucher(node_name: &str, payer: alloy_primitives::Address) -> Result<Option<StoredVoucher>, String> {
+    let body = serde_json::to_vec(&ResellerRequest::GetVoucher(payer)).map_err(|e| e.to_string())?;
+    let response = Request::to(Address::new(node_name, our().process.clone()))
+        .body(body)
+        .send_and_await_response(ROUTED_CALL_TIMEOUT)
+        .map_err(|e| format!("send failed: {e:?}"))?
+        .map_err(|e| format!("send failed: {e:?}"))?;
+    serde_json::from_slice::<Result<Option<StoredVoucher>, String>>(response.body())
+        .map_err(|e| format!("malformed response: {e}"))?
+}
+
+/// Ask the reseller-test process on another node to serve the call with its own keys.
+fn call_reseller(node_name: &str, packet: &ResellerApiPacket) -> Result<ResellerApiResponse, String> {
+    let packet = ResellerApiPacket {
+        routing: None,
+        ..packet.clone()
+    };
+    let body = serde_json::to_vec(&ResellerRequest::ServeApiCall(packet)).map_err(|e| e.to_string())?;
+    let response = Request::to(Address::new(node_name, our().process.clone()))
+        .body(body)
+        .send_and_await_response(ROUTED_CALL_TIMEOUT)
+        .map_err(|e| format!("send failed: {e:?}"))?
+        .map_err(|e| format!("send failed: {e:?}"))?;
+    serde_json::from_slice::<Result<ResellerApiResponse, String>>(response.body())
+        .map_err(|e| format!("malformed response: {e}"))?
+}
@@ -0,0 +1,138 @@
+use std::collections::{
+    BTreeMap,
+    HashMap
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::Value;
+use process_macros::SerdeJsonInto;
+use kinode_process_lib::{
+    our,
+    kiprintln,
+    vfs
+};
+
+use crate::db::SNAPSHOT_TABLES;
+use crate::structs::ResellerState;
+
+/// VFS drive holding exported snapshots and the bootstrap snapshot.
+const SNAPSHOT_DRIVE: &str = "snapshots";
+/// File imported at startup when the index is empty.
+const BOOTSTRAP_FILE: &str = "bootstrap.json";
+/// Bumped whenever the snapshot layout or the set of `SNAPSHOT_TABLES` changes.
+const SNAPSHOT_FORMAT: u32 = 2;
+
+/// The kimap index at a sync cursor, as written to VFS.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexSnapshot {
+    pub format: u32,
+    /// chain and kimap contract the index was built from; imports from another
+    /// chain or contract are refused
+    // defaulted so older formats still parse far enough to be reported as such
+    #[serde(default)]
+    pub chain_id: u64,
+    pub kimap_address: String,
+    pub last_block: u64,
+    pub last_log_index: Option<u64>,
+    /// rows of each table in `SNAPSHOT_TABLES`
+    pub tables: BTreeMap<String, Vec<HashMap<String, Value>>>,
+}
+
+/// Where an export was written.
+#[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
+pub struct SnapshotInfo {
+    pub path: String,
+    pub last_block: u64,
+    /// number of rows written per table
+    pub rows: BTreeMap<String, usize>,
+}
+
+fn snapshot_drive() -> anyhow::Result<String> {
+    vfs::create_drive(our().package_id(), SNAPSHOT_DRIVE, None)
+        .map_err(|e| anyhow::anyhow!("failed to open snapshot drive: {e:?}"))
+}
+
+/// Write the current index and its sync cursor to `snapshots/kimap-<block>.json`.
+///
+/// The file can be handed to another node and placed at `snapshots/bootstrap.json`
+/// there, so it syncs forward from `last_block` instead of from the first kimap block.
+pub fn export_snapshot(state: &ResellerState) -> anyhow::Result<SnapshotInfo> {
+    let mut tables = BTreeMap::new();
+    for (table, columns) in SNAPSHOT_TABLES {
+        tables.insert(table.to_string(), state.db.dump_table(table, columns)?);
+    }
+    let snapshot = IndexSnapshot {
+        format: SNAPSHOT_FORMAT,
+        chain_id: state.config.chain_id,
+        kimap_address: state.kimap.address().to_string(),
+        last_block: state.sync.last_block,
+        last_log_index: state.sync.last_log_index,
+        tables,
+    };
+
+    let path = format!("{}/kimap-{}.json", snapshot_drive()?, snapshot.last_block);
+    let file = vfs::create_file(&path, None)
+        .map_err(|e| anyhow::anyhow!("failed to create {path}: {e:?}"))?;
+    file.write(&serde_json::to_vec(&snapshot)?)
+        .map_err(|e| anyhow::anyhow!("failed to write {path}: {e:?}"))?;
+
+    kiprintln!("exported kimap index at block {} to {path}", snapshot.last_block);
+    Ok(SnapshotInfo {
+        path,
+        last_block: snapshot.last_block,
+        rows: snapshot
+            .tables
+            .iter()
+            .map(|(table, rows)| (table.clone(), rows.len()))
+            .collect(),
+    })
+}
+
+/// Load `snapshots/bootstrap.json` into an empty index and move the sync cursor to
+/// the snapshot's block. Returns whether a snapshot was imported.
+///
+/// Does nothing if the index has already synced past the first kimap block, so a
+/// restarted node never rewinds onto an older snapshot.
+pub fn import_bootstrap_snapshot(state: &mut ResellerState) -> anyhow::Result<bool> {
+    if state.sync.last_block >= state.sync.first_block {
+        return Ok(false);
+    }
+    let path = format!("{}/{}", snapshot_drive()?, BOOTSTRAP_FILE);
+    let Ok(file) = vfs::open_file(&path, false, None) else {
+        return Ok(false);
+    };
+    let bytes = file
+        .read()
+        .map_err(|e| anyhow::anyhow!("failed to read {path}: {e:?}"))?;
+    let snapshot: IndexSnapshot = serde_json::from_slice(&bytes)?;
+
+    if snapshot.format != SNAPSHOT_FORMAT {
+        return Err(anyhow::anyhow!(
+            "snapshot format {} is not supported (expected {SNAPSHOT_FORMAT})",
+            snapshot.format
+        ));
+    }
+    let chain_id = state.config.chain_id;
+    let kimap_address = state.kimap.address().to_string();
+    if snapshot.chain_id != chain_id || !snapshot.kimap_address.eq_ignore_ascii_case(&kimap_address) {
+        return Err(anyhow::anyhow!(
+            "snapshot was built from kimap {} on chain {}, but this node indexes {kimap_address} on chain {chain_id}",
+            snapshot.kimap_address,
+            snapshot.chain_id
+        ));
+    }
+
+    for (table, columns) in SNAPSHOT_TABLES {
+        if let Some(rows) = snapshot.tables.get(*table) {
+            state.db.load_rows(table, columns, rows)?;
+        }
+    }
+    state.sync.last_block = snapshot.last_block;
+    state.sync.last_log_index = snapshot.last_log_index;
+    state.save();
+
+    kiprintln!("imported kimap snapshot from {path}; syncing forward from block {}", snapshot.last_block);
+    Ok(true)
+}
@@ -16,25 +16,108 @@ use kinode_process_lib::{
     kimap
 };
 use alloy_sol_types::SolEvent;
+use alloy_primitives::{Address, B256, U256};
+
+use crate::decoders::{
+    self,
+    DecodedData
+};
+use crate::db::KimapDb;
+use crate::catalog::{index_api_spec, ApiCatalogEntry};
+use crate::routing::{ResellerStats, Routing};
+use crate::offering::{confirm_publication, Offering, Publication};
+use crate::wallet::HotWallet;
+use crate::token::TokenMetadata;
+use crate::vouchers::SignedVoucher;
+use crate::receipts::{now, SignedReceipt};
+use crate::usage_roots::{index_usage_root, UsageCommitment};
+use crate::config::PackageConfig;
+use crate::contracts::{ERC6551Registry, ERC6551_REGISTRY};
+use crate::helpers::ANTHROPIC_MODEL;
 
 use dotenvy::dotenv;
 use std::env;
 
 
+/// Initial block range for a single `get_logs` call during history sync.
+const DEFAULT_SYNC_CHUNK_SIZE: u64 = 50_000;
+/// Smallest block range the sync will shrink to after provider errors.
+const MIN_SYNC_CHUNK_SIZE: u64 = 100;
+/// Largest block range the sync will grow to after successful chunks.
+const MAX_SYNC_CHUNK_SIZE: u64 = 500_000;
+/// Consecutive failed chunks tolerated before a sync attempt gives up.
+const MAX_SYNC_RETRIES: u32 = 8;
+/// Pause between failed chunk fetches.
+const SYNC_RETRY_DELAY_SECS: u64 = 2;
+/// Length of the change feed served by `GetChangesSince`.
+const MAX_CHANGES: usize = 10_000;
+/// Id of our kimap log subscription on `eth:distro:sys`.
+pub const KIMAP_SUBSCRIPTION_ID: u64 = 1;
+/// Id of our subscription to the ERC-6551 registry's account logs for kimap tokens.
+pub const ACCOUNT_SUBSCRIPTION_ID: u64 = 2;
+/// Quiet time after which the subscriptions are assumed silently dropped and renewed.
+/// Renewing backfills from the sync cursor, so doing it needlessly only costs a `get_logs`.
+const SUBSCRIPTION_TIMEOUT_SECS: u64 = 600;
+
 /// The application state.
+///
+/// Saved with field names, so a field added with `#[serde(default)]` still reads the
+/// state a previous version saved. New fields go at the end.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResellerState {
     /// The Kimap instance.
     pub kimap: kimap::Kimap,
-    /// Lookup table from name to namehash.
-    pub names: HashMap<String, String>,
-    /// Map from a namehash to its corresponding node.
-    pub index: BTreeMap<String, Node>,
 
     /// The user-facing API keys.
     pub user_facing_api_keys: Vec<String>,
     /// The remote API keys.
     pub remote_api_keys: HashMap<String, String> ,
+
+    /// Chain and contract settings the index was built with.
+    #[serde(default)]
+    pub config: PackageConfig,
+    /// The kimap index, stored in the sqlite service and reopened on every start.
+    #[serde(skip)]
+    pub db: KimapDb,
+    /// Progress of the historical log sync.
+    pub sync: SyncState,
+    /// Changes not yet pushed to websocket subscribers.
+    #[serde(skip)]
+    pub unpushed_changes: Vec<IndexChange>,
+    /// Set when a log may have changed the reseller directory; it is rebuilt on the next listing.
+    #[serde(skip)]
+    pub directory_stale: bool,
+    /// Measured latency and error rate of each reseller we routed calls to, by reseller name.
+    #[serde(default)]
+    pub reseller_stats: HashMap<String, ResellerStats>,
+    /// What we sell, as published in our API spec.
+    #[serde(default)]
+    pub offering: Option<Offering>,
+    /// The last spec we asked the user to publish, and whether its note has been seen.
+    #[serde(default)]
+    pub publication: Option<Publication>,
+    /// Optional process-managed signing key for sending transactions without a browser wallet.
+    #[serde(default)]
+    pub wallet: Option<HotWallet>,
+    /// Metadata of ERC20s we've read, by token address.
+    #[serde(default)]
+    pub token_metadata: HashMap<Address, TokenMetadata>,
+    /// Cumulative amount of the latest voucher a reseller served a call for, by the tba it pays.
+    #[serde(default)]
+    pub voucher_totals: HashMap<Address, U256>,
+    /// Tbas whose last voucher call failed; the reseller may have charged it anyway, so
+    /// its total is fetched before the next voucher is signed.
+    #[serde(skip)]
+    pub unsynced_vouchers: BTreeSet<Address>,
+    /// Usage roots we computed for our own receipts, by period.
+    #[serde(default)]
+    pub usage_commitments: BTreeMap<u64, UsageCommitment>,
+    /// A complete period found to have no receipts, so it isn't queried again.
+    #[serde(skip)]
+    pub empty_usage_period: Option<u64>,
+    /// Unix seconds of the last message from `eth:distro:sys` on our subscriptions.
+    #[serde(skip)]
+    pub last_eth_message: u64,
 }
 
 impl State for ResellerState {
@@ -64,74 +147,245 @@ impl State for ResellerState {
             }
         }
 
-        let kimap = kimap::Kimap::default(60);
+        let config = PackageConfig::load();
 
-        // Start the subscription loop (with minimal pause parameters).
-        kimap
-            .provider
-            .subscribe_loop(1, Self::make_filter(&kimap), 0, 0);
-
-
-        let mut fresh_state= Self {
-            kimap: kimap.clone(),
-            names: HashMap::from([(String::new(), kimap::KIMAP_ROOT_HASH.to_string())]),
-            index: BTreeMap::from([(
-                kimap::KIMAP_ROOT_HASH.to_string(),
-                Node {
-                    parent_path: String::new(),
-                    name: String::new(),
-                    child_names: BTreeSet::new(),
-                    data_keys: BTreeMap::new(),
-                },
-            )]),
+        Self {
+            kimap: config.kimap(),
+            db: KimapDb::default(),
+            sync: SyncState::new(config.kimap_first_block),
+            config,
+            unpushed_changes: vec![],
+            directory_stale: true,
+            reseller_stats: HashMap::new(),
+            offering: None,
+            publication: None,
+            wallet: None,
+            token_metadata: HashMap::new(),
+            voucher_totals: HashMap::new(),
+            unsynced_vouchers: BTreeSet::new(),
+            usage_commitments: BTreeMap::new(),
+            empty_usage_period: None,
+            last_eth_message: now(),
             user_facing_api_keys: vec![],
             remote_api_keys
-        };
-
-        // Catch up on historical logs.
-        loop {
-            match kimap.provider.get_logs(&Self::make_filter(&kimap)) {
-                Ok(logs) => {
-                    for log in logs {
-                        if let Err(e) = fresh_state.handle_log(&log) {
-                            kiprintln!("log-handling error! {e:?}");
-                        }
-                    }
-                    break;
-                }
-                Err(e) => {
-                    kiprintln!("got eth error while fetching logs: {e:?}, trying again in 5s...");
-                    std::thread::sleep(std::time::Duration::from_secs(5));
-                    continue;
-                }
-            }
         }
-
-        fresh_state
     }
 
 }
 
 impl ResellerState {
     pub fn save(&self) {
-        let serialized_state = rmp_serde::to_vec(self)
+        let serialized_state = rmp_serde::to_vec_named(self)
             .expect("Failed to serialize state with MessagePack");
         set_state(&serialized_state);
     }
 
+    /// Switch to a new package config. A different chain or kimap contract means a
+    /// different index, so the sync starts over (in that deployment's own database).
+    pub fn apply_config(&mut self, config: PackageConfig) {
+        if (config.chain_id, config.kimap_address, config.kimap_first_block)
+            != (self.config.chain_id, self.config.kimap_address, self.config.kimap_first_block)
+        {
+            kiprintln!(
+                "kimap deployment changed to chain {} / {}, restarting sync",
+                config.chain_id,
+                config.kimap_address
+            );
+            self.sync = SyncState::new(config.kimap_first_block);
+        }
+        self.kimap = config.kimap();
+        self.config = config;
+        self.save();
+    }
+
+    /// Subscribe to kimap and tba account logs, replacing any previous subscriptions
+    /// with the same ids.
+    pub fn subscribe(&self) {
+        let _ = self.kimap.provider.unsubscribe(KIMAP_SUBSCRIPTION_ID);
+        self.kimap
+            .provider
+            .subscribe_loop(KIMAP_SUBSCRIPTION_ID, Self::make_filter(&self.kimap), 0, 0);
+        let _ = self.kimap.provider.unsubscribe(ACCOUNT_SUBSCRIPTION_ID);
+        self.kimap
+            .provider
+            .subscribe_loop(ACCOUNT_SUBSCRIPTION_ID, Self::make_account_filter(&self.kimap), 0, 0);
+    }
+
+    /// Recover from a dropped or failed subscription: resubscribe, then backfill
+    /// everything between the sync cursor and the head with `get_logs`.
+    pub fn recover_subscription(&mut self, error: &str) -> anyhow::Result<()> {
+        kiprintln!("kimap subscription failed: {error}; resubscribing from block {}", self.sync.next_block());
+        self.sync.live = false;
+        self.sync.last_error = Some(error.to_string());
+        self.subscribe();
+        self.sync_history()
+    }
+
+    /// Renew the subscriptions if nothing has arrived on them for a while: a provider
+    /// that drops a subscription doesn't always send an error for it.
+    pub fn check_subscription(&mut self) -> anyhow::Result<()> {
+        let quiet = now().saturating_sub(self.last_eth_message);
+        if quiet < SUBSCRIPTION_TIMEOUT_SECS {
+            return Ok(());
+        }
+        self.last_eth_message = now();
+        self.recover_subscription(&format!("nothing received for {quiet}s"))
+    }
+
     pub fn make_filter(kimap: &kimap::Kimap) -> eth::Filter {
+        // block ranges are set by each caller: history sync, audits and the subscription differ
         eth::Filter::new()
             .address(*kimap.address())
-            .from_block(kimap::KIMAP_FIRST_BLOCK)
-            .to_block(eth::BlockNumberOrTag::Latest)
             .events(vec![
                 kimap::contract::Mint::SIGNATURE,
                 kimap::contract::Note::SIGNATURE,
                 kimap::contract::Fact::SIGNATURE,
+                kimap::contract::Gene::SIGNATURE,
+                kimap::contract::Transfer::SIGNATURE,
             ])
     }
 
+    /// `ERC6551AccountCreated` logs for kimap tokens: they carry each entry's tba, which
+    /// kimap's own logs don't. They come from another contract, so they are fetched
+    /// separately and applied regardless of the sync cursor.
+    pub fn make_account_filter(kimap: &kimap::Kimap) -> eth::Filter {
+        eth::Filter::new()
+            .address(ERC6551_REGISTRY)
+            .event(ERC6551Registry::ERC6551AccountCreated::SIGNATURE)
+            .topic2(kimap.address().into_word())
+    }
+
+    /// Catch up on historical logs from the sync cursor to the current head.
+    ///
+    /// Logs are fetched in adaptive chunks (see `get_logs_chunked`). The cursor is saved
+    /// after every chunk, so an interrupted sync resumes where it stopped instead of
+    /// starting over from the first block.
+    pub fn sync_history(&mut self) -> anyhow::Result<()> {
+        let head = self
+            .kimap
+            .provider
+            .get_block_number()
+            .map_err(|e| anyhow::anyhow!("failed to fetch head block: {e:?}"))?;
+        self.sync.head_block = head;
+
+        // the accounts for each chunk's mints are fetched from the same block range
+        let filters = [Self::make_account_filter(&self.kimap), Self::make_filter(&self.kimap)];
+        let from = self.sync.next_block();
+        let result = self.get_logs_chunked(&filters, from, head, |state, logs, to| {
+            let (accounts, logs): (Vec<_>, Vec<_>) = logs
+                .into_iter()
+                .partition(|log| log.address() == ERC6551_REGISTRY);
+            for log in accounts {
+                if let Err(e) = state.apply_log(&log) {
+                    kiprintln!("log-handling error! {e:?}");
+                }
+            }
+            for log in logs {
+                if let Err(e) = state.handle_log(&log) {
+                    kiprintln!("log-handling error! {e:?}");
+                }
+            }
+            state.sync.complete_block(to);
+            state.save();
+            kiprintln!(
+                "synced kimap logs to block {to}/{head} ({:.2}%)",
+                state.sync.percentage()
+            );
+            Ok(())
+        });
+        if let Err(e) = result {
+            self.sync.last_error = Some(e.to_string());
+            self.save();
+            return Err(e);
+        }
+
+        self.sync.live = true;
+        self.sync.last_error = None;
+        Ok(())
+    }
+
+    /// Fetch the logs matching each of `filters` from `from` to `to` in block-range chunks,
+    /// handing each chunk to `on_chunk` along with the last block it covers. A chunk holds
+    /// the logs of every filter, in the order the filters are given.
+    ///
+    /// The chunk size is halved whenever the provider rejects a range for any filter and
+    /// doubled again after each successful chunk. A range that keeps failing is given up
+    /// on after `MAX_SYNC_RETRIES` retries.
+    pub fn get_logs_chunked(
+        &mut self,
+        filters: &[eth::Filter],
+        from: u64,
+        to: u64,
+        mut on_chunk: impl FnMut(&mut Self, Vec<eth::Log>, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut from = from;
+        let mut failures = 0;
+        while from <= to {
+            let end = (from + self.sync.chunk_size - 1).min(to);
+            let fetched = filters.iter().try_fold(vec![], |mut logs, filter| -> Result<_, eth::EthError> {
+                let chunk = filter.clone().from_block(from).to_block(end);
+                logs.extend(self.kimap.provider.get_logs(&chunk)?);
+                Ok(logs)
+            });
+
+            match fetched {
+                Ok(logs) => {
+                    failures = 0;
+                    self.sync.grow_chunk();
+                    on_chunk(self, logs, end)?;
+                    from = end + 1;
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures > MAX_SYNC_RETRIES {
+                        return Err(anyhow::anyhow!(
+                            "giving up on logs at block {from} after {MAX_SYNC_RETRIES} retries: {e:?}"
+                        ));
+                    }
+                    self.sync.shrink_chunk();
+                    kiprintln!(
+                        "got eth error while fetching logs {from}..={end}: {e:?}, retrying with chunk size {} in {}s...",
+                        self.sync.chunk_size,
+                        SYNC_RETRY_DELAY_SECS
+                    );
+                    std::thread::sleep(std::time::Duration::from_secs(SYNC_RETRY_DELAY_SECS));
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn handle_log(&mut self, log: &eth::Log) -> anyhow::Result<()> {
+        // skip logs the cursor has already moved past (e.g. seen both in history and via subscription)
+        if !self.sync.should_apply(log) {
+            return Ok(());
+        }
+        if let (Some(block), Some(log_index)) = (log.block_number, log.log_index) {
+            self.sync.advance(block, log_index);
+        }
+        self.apply_log(log)
+    }
+
+    /// Apply a log to the index regardless of the sync cursor.
+    /// Replaying a log the index already holds leaves it unchanged.
+    pub fn apply_log(&mut self, log: &eth::Log) -> anyhow::Result<()> {
+        // one sqlite transaction per log, so its writes land together or not at all
+        self.db.begin()?;
+        if let Err(e) = self.index_log(log) {
+            self.db.discard();
+            return Err(e);
+        }
+        self.db.commit()?;
+        self.save();
+        Ok(())
+    }
+
+    /// The index writes for one log. Reads don't see writes made earlier in the same
+    /// log, since they are only committed once the whole log is applied.
+    fn index_log(&mut self, log: &eth::Log) -> anyhow::Result<()> {
+        if log.address() == ERC6551_REGISTRY {
+            return self.apply_account_log(log);
+        }
         match log.topics()[0] {
             kimap::contract::Mint::SIGNATURE_HASH => {
                 let decoded = kimap::contract::Mint::decode_log_data(log.data(), true).unwrap();
@@ -140,7 +394,8 @@ impl ResellerState {
                 let child_hash = decoded.childhash.to_string();
                 let label = String::from_utf8(decoded.label.to_vec())?;
 
-                self.add_mint(&parent_hash, child_hash, label)?;
+                let full_name = self.add_mint(&parent_hash, child_hash.clone(), label)?;
+                self.record_change(log, &child_hash, full_name, IndexChangeKind::Mint { parent_hash })?;
             }
             kimap::contract::Note::SIGNATURE_HASH => {
                 let decoded = kimap::contract::Note::decode_log_data(log.data(), true).unwrap();
@@ -148,7 +403,17 @@ impl ResellerState {
                 let parent_hash = decoded.parenthash.to_string();
                 let note_label = String::from_utf8(decoded.label.to_vec())?;
 
-                self.add_note(&parent_hash, note_label, decoded.data)?;
+                if note_label.starts_with('~') && self.is_reseller(&parent_hash)? {
+                    self.directory_stale = true;
+                }
+                let version = self.data_version(log, decoded.data);
+                self.add_note(&parent_hash, note_label.clone(), version.clone())?;
+                self.db.set_data_key_hash(&decoded.notehash.to_string(), &parent_hash, &note_label)?;
+                index_api_spec(self, &parent_hash, &note_label, &version)?;
+                confirm_publication(self, &parent_hash, &note_label, &version);
+                index_usage_root(self, &parent_hash, &note_label, &version)?;
+                let full_name = self.full_name(&parent_hash)?;
+                self.record_change(log, &parent_hash, full_name, IndexChangeKind::Note { label: note_label })?;
             }
             kimap::contract::Fact::SIGNATURE_HASH => {
                 let decoded = kimap::contract::Fact::decode_log_data(log.data(), true).unwrap();
@@ -156,23 +421,137 @@ impl ResellerState {
                 let parent_hash = decoded.parenthash.to_string();
                 let fact_label = String::from_utf8(decoded.label.to_vec())?;
 
-                self.add_fact(&parent_hash, fact_label, decoded.data)?;
+                let version = self.data_version(log, decoded.data);
+                self.add_fact(&parent_hash, fact_label.clone(), version)?;
+                self.db.set_data_key_hash(&decoded.facthash.to_string(), &parent_hash, &fact_label)?;
+                let full_name = self.full_name(&parent_hash)?;
+                self.record_change(log, &parent_hash, full_name, IndexChangeKind::Fact { label: fact_label })?;
+            }
+            kimap::contract::Gene::SIGNATURE_HASH => {
+                let decoded = kimap::contract::Gene::decode_log_data(log.data(), true).unwrap();
+
+                if Some(decoded.gene) == self.config.reseller_impl {
+                    self.directory_stale = true;
+                }
+                self.set_gene(decoded.entry.to_string(), decoded.gene)?;
+            }
+            kimap::contract::Transfer::SIGNATURE_HASH => {
+                let decoded = kimap::contract::Transfer::decode_log_data(log.data(), true).unwrap();
+
+                // kimap token ids are the entries' namehashes
+                let namehash = B256::from(decoded.id.to_be_bytes::<32>()).to_string();
+                if self.is_reseller(&namehash)? {
+                    self.directory_stale = true;
+                }
+
+                self.set_owner(namehash, decoded.to)?;
             }
             _ => {}
         }
-        self.save();
         Ok(())
     }
 
+    /// Record the tba the ERC-6551 registry created for a kimap entry.
+    fn apply_account_log(&mut self, log: &eth::Log) -> anyhow::Result<()> {
+        let decoded = ERC6551Registry::ERC6551AccountCreated::decode_log_data(log.data(), true)?;
+        if decoded.tokenContract != *self.kimap.address() {
+            return Ok(());
+        }
+        // kimap token ids are the entries' namehashes
+        let namehash = B256::from(decoded.tokenId.to_be_bytes::<32>()).to_string();
+        self.db.set_tba(&namehash, decoded.account)
+    }
+
+    /// Append an applied log to the change feed, which keeps the last `MAX_CHANGES`
+    /// entries, and queue it for websocket subscribers.
+    fn record_change(&mut self, log: &eth::Log, namehash: &str, name: String, kind: IndexChangeKind) -> anyhow::Result<()> {
+        let change = IndexChange {
+            block: log.block_number.unwrap_or(self.sync.last_block),
+            log_index: log.log_index,
+            namehash: namehash.to_string(),
+            name,
+            kind,
+        };
+        self.db.insert_change(&change, MAX_CHANGES)?;
+        self.unpushed_changes.push(change);
+        Ok(())
+    }
+
+    /// Full name of an indexed entry, empty if it isn't indexed.
+    fn full_name(&self, namehash: &str) -> anyhow::Result<String> {
+        Ok(self
+            .db
+            .node_row(namehash)?
+            .map(|row| row.full_name)
+            .unwrap_or_default())
+    }
+
+    /// Changes applied after `block`, oldest first.
+    ///
+    /// `complete` is false when the feed no longer reaches back to `block`, in which
+    /// case the client has missed changes and should refetch what it displays.
+    pub fn changes_since(&self, block: u64) -> anyhow::Result<ChangesSince> {
+        // the feed only drops entries once it is full
+        let complete = self.db.change_count()? < MAX_CHANGES
+            || self.db.oldest_change_block()?.is_some_and(|oldest| oldest <= block);
+        Ok(ChangesSince {
+            changes: self.db.changes_since(block)?,
+            current_block: self.sync.last_block,
+            complete,
+        })
+    }
+
+    /// Attach the position and time of a note or fact log to its data.
+    fn data_version(&mut self, log: &eth::Log, data: eth::Bytes) -> DataVersion {
+        let timestamp = match (log.block_timestamp, log.block_number) {
+            (Some(timestamp), _) => Some(timestamp),
+            (None, Some(block)) => self.block_timestamp(block),
+            (None, None) => None,
+        };
+        DataVersion {
+            data,
+            block: log.block_number,
+            tx_hash: log.transaction_hash,
+            log_index: log.log_index,
+            timestamp,
+        }
+    }
+
+    /// Timestamp of a block, for providers that don't include it in logs.
+    /// The last lookup is cached since logs arrive grouped by block.
+    fn block_timestamp(&mut self, block: u64) -> Option<u64> {
+        if let Some((cached_block, timestamp)) = self.sync.block_timestamp {
+            if cached_block == block {
+                return Some(timestamp);
+            }
+        }
+        match self
+            .kimap
+            .provider
+            .get_block_by_number(eth::BlockNumberOrTag::Number(block), false)
+        {
+            Ok(Some(fetched)) => {
+                let timestamp = fetched.header.timestamp;
+                self.sync.block_timestamp = Some((block, timestamp));
+                Some(timestamp)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                kiprintln!("failed to fetch timestamp of block {block}: {e:?}");
+                None
+            }
+        }
+    }
+
     pub fn add_mint(
         &mut self,
         parent_hash: &str,
         child_hash: String,
         name: String,
-    ) -> anyhow::Result<()> {
-        let parent_node: &mut Node = self
-            .index
-            .get_mut(parent_hash)
+    ) -> anyhow::Result<String> {
+        let parent_node = self
+            .db
+            .node_row(parent_hash)?
             .ok_or(anyhow::anyhow!("parent for child {child_hash} not found!"))?;
 
         let parent_path: String = if parent_hash == kimap::KIMAP_ROOT_HASH {
@@ -185,126 +564,167 @@ impl ResellerState {
 
         let full_name = format!("{}{}", name, parent_path);
 
-        parent_node.child_names.insert(full_name.clone());
-        self.names.insert(full_name, child_hash.clone());
-        self.index.insert(
-            child_hash,
-            Node {
-                parent_path,
-                name,
-                child_names: BTreeSet::new(),
-                data_keys: BTreeMap::new(),
-            },
-        );
-        self.save();
-        Ok(())
+        self.db
+            .insert_node(&child_hash, parent_hash, &name, &parent_path, &full_name)?;
+        Ok(full_name)
     }
 
     pub fn add_note(
         &mut self,
         parent_hash: &str,
         note_label: String,
-        data: eth::Bytes,
+        version: DataVersion,
     ) -> anyhow::Result<()> {
-        let parent: &mut Node = self.index.get_mut(parent_hash).ok_or(anyhow::anyhow!(
-            "parent {parent_hash} not found for note {note_label}"
-        ))?;
-
-        match parent.data_keys.entry(note_label) {
-            std::collections::btree_map::Entry::Vacant(e) => {
-                e.insert(DataKey::Note(vec![data]));
-            }
-            std::collections::btree_map::Entry::Occupied(mut e) => {
-                if let DataKey::Note(ref mut notes) = e.get_mut() {
-                    notes.push(data);
-                }
-            }
+        if self.db.node_row(parent_hash)?.is_none() {
+            return Err(anyhow::anyhow!(
+                "parent {parent_hash} not found for note {note_label}"
+            ));
         }
-        self.save();
-        Ok(())
+
+        self.db
+            .insert_version(parent_hash, &note_label, DataKeyKind::Note, &version)
     }
 
     pub fn add_fact(
         &mut self,
         parent_hash: &str,
         fact_label: String,
-        data: eth::Bytes,
+        version: DataVersion,
     ) -> anyhow::Result<()> {
-        let parent: &mut Node = self.index.get_mut(parent_hash).ok_or(anyhow::anyhow!(
-            "parent {parent_hash} not found for fact {fact_label}"
-        ))?;
+        if self.db.node_row(parent_hash)?.is_none() {
+            return Err(anyhow::anyhow!(
+                "parent {parent_hash} not found for fact {fact_label}"
+            ));
+        }
 
-        // this should never ever happen
-        if parent.data_keys.contains_key(&fact_label) {
+        // this should never ever happen, except when replaying a log we already hold
+        if let Some(DataKey::Fact(existing)) = self.db.data_key(parent_hash, &fact_label)? {
+            if (existing.block, existing.log_index) == (version.block, version.log_index) {
+                return Ok(());
+            }
             return Err(anyhow::anyhow!(
                 "fact {fact_label} already exists on parent {parent_hash}"
             ));
         }
 
-        parent.data_keys.insert(fact_label, DataKey::Fact(data));
-        self.save();
-        Ok(())
+        self.db
+            .insert_version(parent_hash, &fact_label, DataKeyKind::Fact, &version)
     }
 
-    pub fn tree(&self, root_hash: &str, nest_level: usize) -> String {
-        let Some(root) = self.index.get(root_hash) else {
-            return String::new();
+    pub fn set_gene(&mut self, namehash: String, gene: Address) -> anyhow::Result<()> {
+        self.db.set_gene(&namehash, gene)
+    }
+
+    /// Whether an entry's tba uses the reseller implementation.
+    pub fn is_reseller(&self, namehash: &str) -> anyhow::Result<bool> {
+        let Some(reseller_impl) = self.config.reseller_impl else {
+            return Ok(false);
         };
+        Ok(self
+            .db
+            .get_ownership(namehash)?
+            .is_some_and(|ownership| ownership.gene == Some(reseller_impl)))
+    }
+
+    /// Record a transfer of an entry's token.
+    /// A transfer to the zero address is a burn and clears the owner.
+    pub fn set_owner(&mut self, namehash: String, owner: Address) -> anyhow::Result<()> {
+        self.db
+            .set_owner(&namehash, (owner != Address::ZERO).then_some(owner))
+    }
 
-        format!(
-            "{}{}{}{}",
-            if root.name.is_empty() {
-                ".".to_string()
-            } else {
-                format!("└─ {}", root.name)
-            },
-            if root.parent_path.is_empty() {
-                String::new()
-            } else {
-                root.parent_path.to_string()
-            },
-            if root.data_keys.is_empty() {
-                String::new()
-            } else {
-                format!(
-                    "\r\n{}",
-                    root.data_keys
-                        .iter()
-                        .map(|(label, data_key)| format!(
-                            "{}└─ {}: {} bytes",
-                            " ".repeat(nest_level * 4),
-                            label,
-                            match data_key {
-                                // note will never have an empty vector
-                                DataKey::Note(notes) => notes.last().unwrap().len(),
-                                DataKey::Fact(bytes) => bytes.len(),
-                            }
-                        ))
-                        .collect::<Vec<String>>()
-                        .join("\r\n")
-                )
-            },
-            if root.child_names.is_empty() {
-                String::new()
-            } else {
-                format!(
-                    "\r\n{}",
-                    root.child_names
-                        .iter()
-                        .map(|name| if let Some(namehash) = self.names.get(name) {
-                            format!(
-                                "{}{}",
-                                " ".repeat(nest_level * 4),
-                                self.tree(namehash, nest_level + 1)
-                            )
-                        } else {
-                            String::new()
-                        })
-                        .collect::<Vec<String>>()
-                        .join("\r\n")
-                )
+    /// Ownership of an entry, filling in anything the logs can't tell us from the chain.
+    ///
+    /// The tba comes from the registry's account logs and the owner from `Transfer`s; the
+    /// chain is only asked about entries whose logs predate the index following accounts.
+    pub fn resolve_ownership(&mut self, namehash: &str) -> anyhow::Result<Ownership> {
+        if let Some(ownership) = self.db.get_ownership(namehash)? {
+            if ownership.tba.is_some() && ownership.owner.is_some() {
+                return Ok(ownership);
+            }
+        }
+
+        let (tba, owner, _data) = self
+            .kimap
+            .get_hash(namehash)
+            .map_err(|e| anyhow::anyhow!("kimap lookup for {namehash} failed: {e:?}"))?;
+
+        self.db.set_tba(namehash, tba)?;
+        let has_owner = matches!(
+            self.db.get_ownership(namehash)?,
+            Some(Ownership { owner: Some(_), .. })
+        );
+        if !has_owner {
+            self.set_owner(namehash.to_string(), owner)?;
+        }
+        self.db
+            .get_ownership(namehash)?
+            .ok_or(anyhow::anyhow!("ownership of {namehash} missing after update"))
+    }
+
+    /// Build a nested view of the index below `root_hash`, down to `depth` levels of children
+    /// and at most `max_nodes` nodes in all.
+    /// Nodes at the depth limit still report their `child_count`, and nodes whose children
+    /// were cut off by `max_nodes` are marked `truncated`, so clients know to expand them.
+    pub fn subtree(&self, root_hash: &str, depth: usize, include_data: bool, max_nodes: usize) -> anyhow::Result<Option<SubtreeNode>> {
+        let mut remaining = max_nodes;
+        self.subtree_within(root_hash, depth, include_data, &mut remaining)
+    }
+
+    fn subtree_within(&self, root_hash: &str, depth: usize, include_data: bool, remaining: &mut usize) -> anyhow::Result<Option<SubtreeNode>> {
+        if *remaining == 0 {
+            return Ok(None);
+        }
+        let Some(root) = self.db.get_node(root_hash)? else {
+            return Ok(None);
+        };
+        *remaining -= 1;
+
+        let data_keys = root
+            .data_keys
+            .iter()
+            .map(|(label, data_key)| {
+                let versions = data_key.versions();
+                // note will never have an empty vector
+                let latest = versions.last().unwrap();
+                DataKeySummary {
+                    label: label.clone(),
+                    kind: match data_key {
+                        DataKey::Fact(_) => DataKeyKind::Fact,
+                        DataKey::Note(_) => DataKeyKind::Note,
+                    },
+                    versions: versions.len(),
+                    size: latest.data.len(),
+                    data: include_data.then(|| decoders::decode(label, &latest.data)),
+                }
+            })
+            .collect();
+
+        let mut children = vec![];
+        let mut truncated = false;
+        if depth > 0 {
+            for name in &root.child_names {
+                if *remaining == 0 {
+                    truncated = true;
+                    break;
+                }
+                let Some(namehash) = self.db.namehash_of(name)? else {
+                    continue;
+                };
+                if let Some(child) = self.subtree_within(&namehash, depth - 1, include_data, remaining)? {
+                    children.push(child);
+                }
             }
-        )
+        }
+
+        Ok(Some(SubtreeNode {
+            name: root.full_name(),
+            namehash: root_hash.to_string(),
+            data_keys,
+            child_count: root.child_names.len(),
+            children,
+            truncated,
+        }))
     }
 
     pub fn add_api_key(&mut self, key: String, value: String) {
@@ -325,12 +745,39 @@ impl ResellerState {
 /// let packet = ResellerApiPacket {
 ///     provider: RemoteApiProvider::Anthropic,
 ///     message: "What is the meaning of life?".to_string(),
+///     model: None,
+///     routing: Some(Routing {
+///         model: "claude-3-5-sonnet-20240620".to_string(),
+///         strategy: RoutingStrategy::Cheapest,
+///     }),
+///     payment: None,
+///     voucher: None,
 /// };
 /// ```
 #[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
 pub struct ResellerApiPacket {
     pub provider: RemoteApiProvider,
     pub message: String,
+    /// model to call; `ANTHROPIC_MODEL` if unset
+    #[serde(default)]
+    pub model: Option<String>,
+    /// route the call to another node's reseller instead of using our own API key
+    #[serde(default)]
+    pub routing: Option<Routing>,
+    /// hash of a transaction paying the serving reseller for this call, sent from the
+    /// calling node's tba or owner
+    #[serde(default)]
+    pub payment: Option<B256>,
+    /// signed IOU paying the serving reseller, instead of `payment`
+    #[serde(default)]
+    pub voucher: Option<SignedVoucher>,
+}
+
+impl ResellerApiPacket {
+    /// The model the call is for.
+    pub fn requested_model(&self) -> &str {
+        self.model.as_deref().unwrap_or(ANTHROPIC_MODEL)
+    }
 }
 
 /// A packet sent from the reseller back to the user
@@ -339,32 +786,47 @@ pub struct ResellerApiPacket {
 /// ```
 /// let packet = ResellerApiResponse {
 ///     response: "The meaning of life is 42".to_string(),
+///     served_by: Some("zapi-registry1111.test-api-registry.os".to_string()),
+///     receipt: None,
 /// };
 /// ```
 #[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
 pub struct ResellerApiResponse {
-    pub response: String
+    pub response: String,
+    /// the reseller a routed call was served by; `None` if we served it ourselves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<String>,
+    /// what the serving reseller charged, signed by its node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<SignedReceipt>,
 }
 
-/// A response from the reseller to the user
+/// The API catalog, as returned to the user
 /// 
 /// # Example
 /// ```
 /// let response = ListAllAPIsResponse {
-///     apis: vec![' "title": "LLM Proxy API",
-///     "version": "1.0.0",
-///     "description": "A proxy service that standardizes interactions with various LLM providers.\nCurrently supports Anthropic and OpenAI as providers.",
-///     "x-api-type": "REST",
-///     "x-protocol": "HTTP",
-///     "x-architecture": {
-///       "type": "Microservice",
-///       "style": "Request-Response"
-///     }'],
+///     apis: vec![ApiCatalogEntry {
+///         reseller: "zapi-registry1111.test-api-registry.os".to_string(),
+///         namehash: "0x...".to_string(),
+///         api: "~llm-proxy".to_string(),
+///         block: Some(129_000_000),
+///         summary: ApiSummary {
+///             title: Some("LLM Proxy API".to_string()),
+///             version: Some("1.0.0".to_string()),
+///             api_type: Some("REST".to_string()),
+///             protocol: Some("HTTP".to_string()),
+///             provider: Some("anthropic".to_string()),
+///             models: vec!["claude-3-5-sonnet-20241022".to_string()],
+///             ..Default::default()
+///         },
+///         errors: vec![],
+///     }],
 /// };
 /// ```
 #[derive(Debug, Serialize, Deserialize, SerdeJsonInto, Clone)]
 pub struct ListAllAPIsResponse {
-    pub apis: Vec<String>
+    pub apis: Vec<ApiCatalogEntry>
 }
 
 /// A response from the remote API
@@ -491,17 +953,361 @@ pub struct Node {
     pub data_keys: BTreeMap<String, DataKey>,
 }
 
+impl Node {
+    /// The node's full dotted name, e.g. "zapi-registry1111.test-api-registry.os".
+    pub fn full_name(&self) -> String {
+        format!("{}{}", self.name, self.parent_path)
+    }
+}
+
+/// Ownership of a kimap entry, built from `Gene` and `Transfer` logs
+#[derive(Debug, Default, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct Ownership {
+    /// current holder of the entry's token; `None` before its first transfer or after a burn
+    pub owner: Option<Address>,
+    /// implementation of the entry's token-bound account
+    pub gene: Option<Address>,
+    /// the entry's token-bound account, fetched from the chain once and cached
+    pub tba: Option<Address>,
+}
+
+/// A name held by an owner address, as returned by `GetNamesByOwner`.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct OwnedName {
+    pub name: String,
+    pub namehash: String,
+    pub tba: Option<Address>,
+    pub gene: Option<Address>,
+}
+
 /// A key in the node's data keys
 /// 
 /// Either a kimap Fact or a kimap Note
 #[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
 pub enum DataKey {
     /// facts are immutable
-    Fact(eth::Bytes),
+    Fact(DataVersion),
     /// notes are mutable: we store all versions of the note, most recent last
     /// if indexing full history, this will be the note's full history --
     /// it is also possible to receive a snapshot and not have updates from before that.
-    Note(Vec<eth::Bytes>),
+    Note(Vec<DataVersion>),
+}
+
+impl DataKey {
+    /// All versions of the key, oldest first. A fact has exactly one.
+    pub fn versions(&self) -> &[DataVersion] {
+        match self {
+            DataKey::Fact(fact) => std::slice::from_ref(fact),
+            DataKey::Note(notes) => notes,
+        }
+    }
+}
+
+/// One written value of a note or fact, with where and when it was written.
+///
+/// Position fields are `None` only for data that didn't come from a mined log.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct DataVersion {
+    pub data: eth::Bytes,
+    pub block: Option<u64>,
+    pub tx_hash: Option<B256>,
+    pub log_index: Option<u64>,
+    /// unix timestamp of the block, in seconds
+    pub timestamp: Option<u64>,
+}
+
+/// An applied mint, note or fact, as served by the change feed.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct IndexChange {
+    pub block: u64,
+    pub log_index: Option<u64>,
+    /// the minted entry, or the entry the note or fact was written on
+    pub namehash: String,
+    pub name: String,
+    pub kind: IndexChangeKind,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub enum IndexChangeKind {
+    Mint { parent_hash: String },
+    Note { label: String },
+    Fact { label: String },
+}
+
+/// Request changes applied after a block.
+///
+/// # Example
+/// ```
+/// let request = GetChangesSince { block: 129_000_000 };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct GetChangesSince {
+    pub block: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ChangesSince {
+    pub changes: Vec<IndexChange>,
+    /// pass this as `block` in the next request
+    pub current_block: u64,
+    /// false if older changes were dropped from the feed and the client should refetch
+    pub complete: bool,
+}
+
+/// Request a whole branch of the index in one round trip.
+///
+/// # Example
+/// ```
+/// let request = SubtreeRequest {
+///     root: "test-api-registry.os".to_string(),
+///     depth: 2,
+///     include_data: true,
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct SubtreeRequest {
+    /// full name of the branch root; the empty string is the kimap root
+    pub root: String,
+    /// levels of children to include below the root
+    pub depth: usize,
+    /// include each data key's latest decoded value, not just its summary
+    pub include_data: bool,
+}
+
+/// A node of a `GetSubtree` response.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct SubtreeNode {
+    pub name: String,
+    pub namehash: String,
+    pub data_keys: Vec<DataKeySummary>,
+    /// number of direct children, whether or not they are included below
+    pub child_count: usize,
+    pub children: Vec<SubtreeNode>,
+    /// whether children were left out because the response reached its node limit
+    pub truncated: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone, Copy, PartialEq)]
+pub enum DataKeyKind {
+    Fact,
+    Note,
+}
+
+/// A data key as summarized in a subtree.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct DataKeySummary {
+    pub label: String,
+    pub kind: DataKeyKind,
+    /// number of indexed versions; always 1 for a fact
+    pub versions: usize,
+    /// size of the latest version in bytes
+    pub size: usize,
+    /// latest version, decoded; only set when `include_data` was requested
+    pub data: Option<DecodedData>,
+}
+
+/// Request the version history of a note or fact on a named entry.
+///
+/// # Example
+/// ```
+/// let request = NoteHistoryRequest {
+///     name: "my-reseller.zapi-registry1111.test-api-registry.os".to_string(),
+///     label: "~api-spec".to_string(),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct NoteHistoryRequest {
+    pub name: String,
+    pub label: String,
+}
+
+/// All indexed versions of a note or fact, oldest first.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct NoteHistory {
+    pub name: String,
+    pub label: String,
+    pub versions: Vec<DataVersion>,
+}
+
+/// How a name search matches its query against indexed names.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone, Copy, PartialEq)]
+pub enum NameMatch {
+    /// the full name starts with the query
+    Prefix,
+    /// the query appears anywhere in the full name
+    Substring,
+}
+
+/// Search the kimap index by name.
+///
+/// # Example
+/// ```
+/// let search = NameSearch {
+///     query: "zapi".to_string(),
+///     mode: NameMatch::Prefix,
+///     limit: Some(20),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct NameSearch {
+    pub query: String,
+    pub mode: NameMatch,
+    pub limit: Option<usize>,
+}
+
+/// A full kimap name and its namehash.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct NameEntry {
+    pub name: String,
+    pub namehash: String,
+}
+
+/// Request a page of a node's children, in name order.
+///
+/// # Example
+/// ```
+/// let request = ListChildren {
+///     parent: "test-api-registry.os".to_string(),
+///     offset: 0,
+///     limit: Some(50),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ListChildren {
+    /// full name of the parent; the empty string is the kimap root
+    pub parent: String,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// A page of children returned for `ListChildren`.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ChildrenPage {
+    pub parent: String,
+    /// total number of children, across all pages
+    pub total: usize,
+    pub offset: usize,
+    pub children: Vec<NameEntry>,
+}
+
+/// Progress of the kimap log indexer.
+///
+/// The cursor is `(last_block, last_log_index)`: every log up to and including
+/// `last_block` has been applied, except when `last_log_index` is set, in which case
+/// `last_block` has only been applied up to that log.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct SyncState {
+    /// first block of the kimap contract; the sync starts here
+    pub first_block: u64,
+    /// last block applied to the index
+    pub last_block: u64,
+    /// last applied log within `last_block`, if that block is only partially applied
+    pub last_log_index: Option<u64>,
+    /// most recent chain head seen by the sync
+    pub head_block: u64,
+    /// current `get_logs` block range
+    pub chunk_size: u64,
+    /// most recent sync or subscription error, cleared once the sync catches up again
+    pub last_error: Option<String>,
+    /// true once history has caught up and the subscription alone keeps the index current
+    #[serde(skip)]
+    pub live: bool,
+    /// most recently fetched `(block, timestamp)`
+    #[serde(skip)]
+    pub block_timestamp: Option<(u64, u64)>,
+}
+
+impl SyncState {
+    pub fn new(first_block: u64) -> Self {
+        Self {
+            first_block,
+            last_block: first_block.saturating_sub(1),
+            last_log_index: None,
+            head_block: 0,
+            chunk_size: DEFAULT_SYNC_CHUNK_SIZE,
+            last_error: None,
+            live: false,
+            block_timestamp: None,
+        }
+    }
+
+    /// The first block whose logs still need to be fetched.
+    pub fn next_block(&self) -> u64 {
+        if self.last_log_index.is_some() {
+            self.last_block
+        } else {
+            self.last_block + 1
+        }
+    }
+
+    /// Whether a log lies past the cursor. Logs without a position are always applied.
+    pub fn should_apply(&self, log: &eth::Log) -> bool {
+        let (Some(block), Some(log_index)) = (log.block_number, log.log_index) else {
+            return true;
+        };
+        if block != self.last_block {
+            return block > self.last_block;
+        }
+        self.last_log_index.is_some_and(|last| log_index > last)
+    }
+
+    /// Move the cursor to a single applied log.
+    pub fn advance(&mut self, block: u64, log_index: u64) {
+        self.last_block = block;
+        self.last_log_index = Some(log_index);
+        self.head_block = self.head_block.max(block);
+    }
+
+    /// Double the `get_logs` block range after a successful fetch.
+    pub fn grow_chunk(&mut self) {
+        self.chunk_size = (self.chunk_size * 2).min(MAX_SYNC_CHUNK_SIZE);
+    }
+
+    /// Halve the `get_logs` block range after the provider rejects one.
+    pub fn shrink_chunk(&mut self) {
+        self.chunk_size = (self.chunk_size / 2).max(MIN_SYNC_CHUNK_SIZE);
+    }
+
+    /// Mark every log up to and including `block` as applied.
+    pub fn complete_block(&mut self, block: u64) {
+        self.last_block = block;
+        self.last_log_index = None;
+    }
+
+    /// Percentage of the chain between `first_block` and `head_block` that has been indexed.
+    /// Zero until the head block is known.
+    pub fn percentage(&self) -> f64 {
+        if self.head_block == 0 {
+            return 0.0;
+        }
+        if self.head_block <= self.first_block {
+            return 100.0;
+        }
+        let done = self.last_block.saturating_sub(self.first_block) as f64;
+        let total = (self.head_block - self.first_block) as f64;
+        (done / total * 100.0).min(100.0)
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        SyncStatus {
+            current_block: self.last_block,
+            head_block: self.head_block,
+            percentage: self.percentage(),
+            live: self.live,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+/// Sync progress as reported to the UI.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct SyncStatus {
+    pub current_block: u64,
+    pub head_block: u64,
+    pub percentage: f64,
+    /// true once the index is following the chain head through the subscription
+    pub live: bool,
+    pub last_error: Option<String>,
 }
 
 /// update api key. Which provider, and the value of the key.
@@ -509,4 +1315,64 @@ pub enum DataKey {
 pub struct ApiKeyUpdate {
     pub provider: RemoteApiProvider,
     pub key: String,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_shrinks_to_the_minimum_and_stops() {
+        let mut sync = SyncState::new(1);
+        assert_eq!(sync.chunk_size, DEFAULT_SYNC_CHUNK_SIZE);
+        sync.shrink_chunk();
+        assert_eq!(sync.chunk_size, DEFAULT_SYNC_CHUNK_SIZE / 2);
+        for _ in 0..64 {
+            sync.shrink_chunk();
+        }
+        assert_eq!(sync.chunk_size, MIN_SYNC_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn chunk_grows_to_the_maximum_and_stops() {
+        let mut sync = SyncState::new(1);
+        sync.grow_chunk();
+        assert_eq!(sync.chunk_size, DEFAULT_SYNC_CHUNK_SIZE * 2);
+        for _ in 0..64 {
+            sync.grow_chunk();
+        }
+        assert_eq!(sync.chunk_size, MAX_SYNC_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn chunk_recovers_after_backing_off() {
+        let mut sync = SyncState::new(1);
+        for _ in 0..64 {
+            sync.shrink_chunk();
+        }
+        while sync.chunk_size < DEFAULT_SYNC_CHUNK_SIZE {
+            sync.grow_chunk();
+        }
+        assert!(sync.chunk_size <= MAX_SYNC_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn cursor_resumes_inside_a_partially_applied_block() {
+        let mut sync = SyncState::new(100);
+        assert_eq!(sync.next_block(), 100);
+        sync.advance(120, 3);
+        assert_eq!(sync.next_block(), 120);
+        sync.complete_block(120);
+        assert_eq!(sync.next_block(), 121);
+    }
+
+    #[test]
+    fn percentage_is_zero_until_the_head_is_known() {
+        let mut sync = SyncState::new(100);
+        assert_eq!(sync.percentage(), 0.0);
+        sync.head_block = 200;
+        sync.complete_block(150);
+        assert_eq!(sync.percentage(), 50.0);
+        sync.head_block = 100;
+        assert_eq!(sync.percentage(), 100.0);
+    }
+}
@@ -0,0 +1,176 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{Address, U256};
+
+use crate::contracts::{
+    eth_call,
+    ERC20
+};
+use crate::structs::ResellerState;
+
+/// An ERC20's metadata. It never changes, so it is read once per token and cached in state.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct TokenMetadata {
+    pub address: Address,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Read the payment token balance of a reseller entry's tba.
+///
+/// # Example
+/// ```
+/// let request = GetTokenBalance {
+///     name: Some("zapi-registry1111.test-api-registry.os".to_string()),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct GetTokenBalance {
+    /// full name of the entry; defaults to our offering's reseller entry
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct TokenBalance {
+    pub token: TokenMetadata,
+    pub name: String,
+    pub holder: Address,
+    /// in base units
+    pub balance: U256,
+    /// in whole tokens, e.g. "12.5"
+    pub formatted: String,
+}
+
+/// Metadata of the configured payment token, from the cache or the chain.
+pub fn payment_token(state: &mut ResellerState) -> anyhow::Result<TokenMetadata> {
+    let address = state
+        .config
+        .payment_token
+        .ok_or_else(|| anyhow::anyhow!("no payment_token in the package config"))?;
+    token_metadata(state, address)
+}
+
+pub fn token_metadata(state: &mut ResellerState, address: Address) -> anyhow::Result<TokenMetadata> {
+    if let Some(metadata) = state.token_metadata.get(&address) {
+        return Ok(metadata.clone());
+    }
+    let provider = &state.kimap.provider;
+    let metadata = TokenMetadata {
+        address,
+        name: eth_call(provider, address, ERC20::nameCall {})?._0,
+        symbol: eth_call(provider, address, ERC20::symbolCall {})?._0,
+        decimals: eth_call(provider, address, ERC20::decimalsCall {})?._0,
+    };
+    state.token_metadata.insert(address, metadata.clone());
+    state.save();
+    Ok(metadata)
+}
+
+pub fn get_token_balance(state: &mut ResellerState, request: GetTokenBalance) -> anyhow::Result<TokenBalance> {
+    let name = request
+        .name
+        .or_else(|| state.offering.as_ref().map(|offering| offering.reseller.clone()))
+        .ok_or_else(|| anyhow::anyhow!("no name given and no offering set"))?;
+    let namehash = state
+        .db
+        .namehash_of(&name)?
+        .ok_or_else(|| anyhow::anyhow!("Name not found: {name}"))?;
+    let holder = state
+        .resolve_ownership(&namehash)?
+        .tba
+        .ok_or_else(|| anyhow::anyhow!("{name} has no tba"))?;
+    let token = payment_token(state)?;
+    let balance = eth_call(&state.kimap.provider, token.address, ERC20::balanceOfCall { owner: holder })?._0;
+    Ok(TokenBalance {
+        formatted: format_units(balance, token.decimals),
+        token,
+        name,
+        holder,
+        balance,
+    })
+}
+
+/// Parse a decimal amount like "12.5" into base units, exactly. Fails rather than
+/// rounds if the amount has more decimal places than the token.
+pub fn parse_units(amount: &str, decimals: u8) -> anyhow::Result<U256> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(anyhow::anyhow!("empty amount"));
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(anyhow::anyhow!("invalid amount {amount:?}"));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(anyhow::anyhow!("{amount} has more than {decimals} decimal places"));
+    }
+
+    let digits = |digits: &str| -> anyhow::Result<U256> {
+        if digits.is_empty() {
+            return Ok(U256::ZERO);
+        }
+        U256::from_str_radix(digits, 10).map_err(|e| anyhow::anyhow!("invalid amount {amount:?}: {e}"))
+    };
+    let scale = U256::from(10).pow(U256::from(decimals));
+    let fraction = format!("{fraction:0<width$}", width = decimals as usize);
+    digits(whole)?
+        .checked_mul(scale)
+        .and_then(|whole| whole.checked_add(digits(&fraction).ok()?))
+        .ok_or_else(|| anyhow::anyhow!("{amount} overflows uint256"))
+}
+
+/// Format base units as a decimal amount, without trailing zeros.
+pub fn format_units(amount: U256, decimals: u8) -> String {
+    let scale = U256::from(10).pow(U256::from(decimals));
+    let (whole, fraction) = amount.div_rem(scale);
+    if fraction.is_zero() {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0>width$}", fraction.to_string(), width = decimals as usize);
+    format!("{whole}.{}", fraction.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_whole_and_fractional_amounts() {
+        assert_eq!(parse_units("1", 6).unwrap(), U256::from(1_000_000));
+        assert_eq!(parse_units("1.5", 6).unwrap(), U256::from(1_500_000));
+        assert_eq!(parse_units(".25", 6).unwrap(), U256::from(250_000));
+        assert_eq!(parse_units("3.", 6).unwrap(), U256::from(3_000_000));
+        assert_eq!(parse_units(" 0.000001 ", 6).unwrap(), U256::from(1));
+        assert_eq!(parse_units("42", 0).unwrap(), U256::from(42));
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        for amount in ["", ".", "-1", "1e6", "1,5", "0x10", "1.2.3"] {
+            assert!(parse_units(amount, 6).is_err(), "{amount:?}");
+        }
+        assert!(parse_units("0.0000001", 6).is_err());
+        assert!(parse_units(&"9".repeat(80), 18).is_err());
+    }
+
+    #[test]
+    fn formats_without_trailing_zeros() {
+        assert_eq!(format_units(U256::ZERO, 6), "0");
+        assert_eq!(format_units(U256::from(1_000_000), 6), "1");
+        assert_eq!(format_units(U256::from(1_500_000), 6), "1.5");
+        assert_eq!(format_units(U256::from(1), 6), "0.000001");
+        assert_eq!(format_units(U256::from(42), 0), "42");
+    }
+
+    #[test]
+    fn format_then_parse_round_trips() {
+        for amount in [0u64, 1, 10, 999_999, 1_000_001, 123_456_789] {
+            let amount = U256::from(amount);
+            assert_eq!(parse_units(&format_units(amount, 6), 6).unwrap(), amount);
+        }
+    }
+}
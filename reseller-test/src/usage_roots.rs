@@ -0,0 +1,539 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{keccak256, B256};
+use kinode_process_lib::{
+    our,
+    kiprintln,
+    Address as ProcessAddress,
+    Request
+};
+
+use crate::contracts::{
+    note_transaction,
+    UnsignedTransaction
+};
+use crate::function_signatures::ResellerRequest;
+use crate::offering::PublicationStatus;
+use crate::payments::reseller_tba;
+use crate::receipts::{
+    now,
+    ListReceipts,
+    SignedReceipt
+};
+use crate::structs::{
+    DataVersion,
+    ResellerState
+};
+use crate::wallet::{
+    send_transaction,
+    TransactionStatus
+};
+
+/// Note label usage roots are published under.
+pub const USAGE_ROOT_LABEL: &str = "~usage-root";
+/// Seconds to wait for another reseller to answer a proof request.
+const PROOF_REQUEST_TIMEOUT: u64 = 30;
+/// How long a sent usage root may stay unmined before we take it as dropped and send it again.
+const COMMITMENT_TIMEOUT_SECS: u64 = 3_600;
+
+/// What a `~usage-root` note holds: the Merkle root of the receipts a reseller issued
+/// in one period.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone, PartialEq)]
+pub struct UsageRootNote {
+    pub period: u64,
+    /// unix seconds, inclusive
+    pub from: u64,
+    /// unix seconds, exclusive
+    pub to: u64,
+    pub root: B256,
+    pub leaves: u64,
+}
+
+/// A usage root this node computed for its own receipts.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct UsageCommitment {
+    #[serde(flatten)]
+    pub note: UsageRootNote,
+    /// publishes the note through our reseller tba
+    pub transaction: UnsignedTransaction,
+    /// set if the hot wallet sent `transaction`
+    pub sent: Option<B256>,
+    /// unix seconds `sent` was sent at
+    #[serde(default)]
+    pub sent_at: Option<u64>,
+    pub status: PublicationStatus,
+}
+
+/// A usage root indexed from any reseller's `~usage-root` note.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct IndexedUsageRoot {
+    pub reseller: String,
+    pub namehash: String,
+    #[serde(flatten)]
+    pub note: UsageRootNote,
+    pub block: Option<u64>,
+}
+
+/// Commit our receipts for a period.
+///
+/// # Example
+/// ```
+/// let request = CommitUsage { period: None };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct CommitUsage {
+    /// defaults to the last complete period
+    pub period: Option<u64>,
+}
+
+/// Fetch the inclusion proof of a receipt. Only served on the authenticated path: a
+/// proof carries the whole signed receipt, whichever customer it was issued to.
+///
+/// # Example
+/// ```
+/// let request = GetUsageProof {
+///     request_id: "5f0c...".to_string(),
+///     reseller: Some("seller.os".to_string()),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct GetUsageProof {
+    pub request_id: String,
+    /// node that issued the receipt; defaults to ours
+    pub reseller: Option<String>,
+}
+
+/// A receipt's place in its period's Merkle tree.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct UsageProof {
+    pub receipt: SignedReceipt,
+    pub period: u64,
+    pub leaf: B256,
+    /// sibling hashes from the leaf up; pairs are hashed in sorted order
+    pub proof: Vec<B256>,
+    /// root the proof leads to
+    pub root: B256,
+    /// the reseller's published root for the period, if the index has one
+    pub published_root: Option<B256>,
+    /// the proof leads to the published root
+    pub verified: bool,
+}
+
+/// List indexed usage roots.
+///
+/// # Example
+/// ```
+/// let request = ListUsageRoots {
+///     reseller: Some("zapi-registry1111.test-api-registry.os".to_string()),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct ListUsageRoots {
+    /// full name of the reseller entry
+    pub reseller: Option<String>,
+}
+
+impl UsageRootNote {
+    /// Seconds in the period, as configured when the root was computed.
+    pub fn length(&self) -> u64 {
+        self.to - self.from
+    }
+}
+
+/// The Merkle leaf of a receipt.
+pub fn leaf(signed: &SignedReceipt) -> anyhow::Result<B256> {
+    Ok(keccak256(signed.receipt.signing_bytes()?))
+}
+
+fn hash_pair(a: B256, b: B256) -> B256 {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    keccak256([low.as_slice(), high.as_slice()].concat())
+}
+
+/// Each level of the tree, leaves first. A node without a sibling moves up unchanged.
+fn levels(leaves: &[B256]) -> Vec<Vec<B256>> {
+    let mut levels = vec![leaves.to_vec()];
+    while levels.last().is_some_and(|level| level.len() > 1) {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => hash_pair(*a, *b),
+                [a] => *a,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Root of the tree; zero for a period without receipts.
+pub fn merkle_root(leaves: &[B256]) -> B256 {
+    levels(leaves)
+        .last()
+        .and_then(|level| level.first().copied())
+        .unwrap_or_default()
+}
+
+pub fn merkle_proof(leaves: &[B256], mut index: usize) -> Vec<B256> {
+    let mut proof = vec![];
+    for level in levels(leaves).iter().filter(|level| level.len() > 1) {
+        if let Some(sibling) = level.get(index ^ 1) {
+            proof.push(*sibling);
+        }
+        index /= 2;
+    }
+    proof
+}
+
+pub fn verify_proof(leaf: B256, proof: &[B256], root: B256) -> bool {
+    proof.iter().fold(leaf, |hash, sibling| hash_pair(hash, *sibling)) == root
+}
+
+fn period_of(state: &ResellerState, timestamp: u64) -> anyhow::Result<u64> {
+    match state.config.usage_period {
+        0 => Err(anyhow::anyhow!("usage_period is 0 in the package config")),
+        period => Ok(timestamp / period),
+    }
+}
+
+/// Leaves of the receipts we issued in a period of `length` seconds, in issue order.
+fn period_receipts(state: &ResellerState, period: u64, length: u64) -> anyhow::Result<(UsageRootNote, Vec<SignedReceipt>)> {
+    let receipts = state.db.receipts(&ListReceipts {
+        customer: None,
+        reseller: Some(our().node.clone()),
+        from: Some(period * length),
+        to: Some((period + 1) * length),
+    })?;
+    let leaves = receipts.iter().map(leaf).collect::<anyhow::Result<Vec<B256>>>()?;
+    let note = UsageRootNote {
+        period,
+        from: period * length,
+        to: (period + 1) * length,
+        root: merkle_root(&leaves),
+        leaves: leaves.len() as u64,
+    };
+    Ok((note, receipts))
+}
+
+/// Compute a period's usage root and the transaction that publishes it on our reseller
+/// entry. The hot wallet sends it if it is unlocked.
+pub fn commit_usage(state: &mut ResellerState, request: CommitUsage) -> anyhow::Result<UsageCommitment> {
+    let period = match request.period {
+        Some(period) => period,
+        None => period_of(state, now())?.saturating_sub(1),
+    };
+    if period >= period_of(state, now())? {
+        return Err(anyhow::anyhow!("period {period} isn't over yet"));
+    }
+    let reseller = state
+        .offering
+        .as_ref()
+        .map(|offering| offering.reseller.clone())
+        .ok_or_else(|| anyhow::anyhow!("no offering set, so there is no entry to publish on"))?;
+    let tba = reseller_tba(state)?;
+    let (note, _) = period_receipts(state, period, state.config.usage_period)?;
+
+    let transaction = note_transaction(
+        state.config.chain_id,
+        state.config.kimap_address,
+        tba,
+        USAGE_ROOT_LABEL,
+        serde_json::to_vec(&note)?,
+        format!("{USAGE_ROOT_LABEL} for period {period} on {reseller}"),
+    );
+    let unlocked = state.wallet.as_ref().is_some_and(|wallet| wallet.signer.is_some());
+    let sent = if unlocked {
+        Some(send_transaction(state, transaction.clone())?.hash)
+    } else {
+        None
+    };
+    let commitment = UsageCommitment {
+        note,
+        transaction,
+        sent,
+        sent_at: sent.map(|_| now()),
+        status: PublicationStatus::Pending,
+    };
+    kiprintln!(
+        "usage root for period {period}: {} over {} receipts",
+        commitment.note.root,
+        commitment.note.leaves
+    );
+    state.usage_commitments.insert(period, commitment.clone());
+    state.save();
+    Ok(commitment)
+}
+
+/// Commit the last complete period if it had receipts and isn't committed yet, and
+/// send again any commitment that didn't make it on chain. Only sends anything when
+/// the hot wallet is unlocked.
+pub fn maybe_commit_usage(state: &mut ResellerState) {
+    let unlocked = state.wallet.as_ref().is_some_and(|wallet| wallet.signer.is_some());
+    if !unlocked || state.offering.is_none() || state.config.usage_period == 0 {
+        return;
+    }
+    retry_commitments(state);
+    let Ok(period) = period_of(state, now()).map(|period| period.saturating_sub(1)) else {
+        return;
+    };
+    // a complete period never gains receipts, so an empty one stays empty
+    if state.usage_commitments.contains_key(&period) || state.empty_usage_period == Some(period) {
+        return;
+    }
+    match period_receipts(state, period, state.config.usage_period) {
+        Ok((note, _)) if note.leaves == 0 => state.empty_usage_period = Some(period),
+        Ok(_) => {
+            if let Err(e) = commit_usage(state, CommitUsage { period: Some(period) }) {
+                kiprintln!("failed to commit usage for period {period}: {e}");
+            }
+        }
+        Err(e) => kiprintln!("failed to read receipts for period {period}: {e}"),
+    }
+}
+
+/// Send again every pending commitment that was never sent, whose transaction reverted,
+/// or that has gone unmined for `COMMITMENT_TIMEOUT_SECS`.
+fn retry_commitments(state: &mut ResellerState) {
+    let stale: Vec<(u64, bool)> = state
+        .usage_commitments
+        .iter()
+        .filter(|(_, commitment)| matches!(commitment.status, PublicationStatus::Pending))
+        .filter_map(|(period, commitment)| {
+            let Some(hash) = commitment.sent else {
+                return Some((*period, false));
+            };
+            let status = state
+                .wallet
+                .as_ref()
+                .and_then(|wallet| wallet.transactions.iter().find(|tx| tx.hash == hash))
+                .map(|tx| tx.status.clone());
+            match status {
+                Some(TransactionStatus::Mined { success, .. }) => (!success).then_some((*period, false)),
+                Some(TransactionStatus::Pending) => commitment
+                    .sent_at
+                    .is_some_and(|sent_at| now().saturating_sub(sent_at) > COMMITMENT_TIMEOUT_SECS)
+                    .then_some((*period, true)),
+                // the wallet that sent it was replaced
+                None => Some((*period, false)),
+            }
+        })
+        .collect();
+
+    for (period, dropped) in stale {
+        let Some(transaction) = state.usage_commitments.get(&period).map(|commitment| commitment.transaction.clone()) else {
+            continue;
+        };
+        if dropped {
+            // a dropped transaction leaves its nonce unused; ask the chain for it again
+            if let Some(wallet) = state.wallet.as_mut() {
+                wallet.next_nonce = None;
+            }
+        }
+        kiprintln!("usage root for period {period} isn't on chain yet; sending it again");
+        match send_transaction(state, transaction) {
+            Ok(tracked) => {
+                if let Some(commitment) = state.usage_commitments.get_mut(&period) {
+                    commitment.sent = Some(tracked.hash);
+                    commitment.sent_at = Some(now());
+                }
+                state.save();
+            }
+            Err(e) => kiprintln!("failed to resend usage root for period {period}: {e}"),
+        }
+    }
+}
+
+/// Index a `~usage-root` note from any reseller, and confirm our own commitment if it
+/// is the note we asked for.
+pub fn index_usage_root(state: &mut ResellerState, namehash: &str, label: &str, version: &DataVersion) -> anyhow::Result<()> {
+    if label != USAGE_ROOT_LABEL {
+        return Ok(());
+    }
+    let Ok(note) = serde_json::from_slice::<UsageRootNote>(&version.data) else {
+        return Ok(());
+    };
+    let Some(reseller) = state.db.node_row(namehash)? else {
+        return Ok(());
+    };
+    if let Some(commitment) = state.usage_commitments.get_mut(&note.period) {
+        let ours = state.offering.as_ref().is_some_and(|offering| offering.reseller == reseller.full_name);
+        if ours && commitment.note == note {
+            commitment.status = PublicationStatus::Published {
+                block: version.block,
+                tx_hash: version.tx_hash,
+            };
+            kiprintln!("usage root for period {} published in block {:?}", note.period, version.block);
+        }
+    }
+    state.db.upsert_usage_root(&IndexedUsageRoot {
+        reseller: reseller.full_name,
+        namehash: namehash.to_string(),
+        note,
+        block: version.block,
+    })
+}
+
+/// Index the `~usage-root` notes already in the index. Each version of the note is a
+/// period, so all of them are replayed, oldest first.
+pub fn reindex_usage_roots(state: &mut ResellerState) -> anyhow::Result<()> {
+    if state.db.has_usage_roots()? {
+        return Ok(());
+    }
+    let versions = state.db.note_versions_labelled(USAGE_ROOT_LABEL)?;
+    for (namehash, version) in &versions {
+        index_usage_root(state, namehash, USAGE_ROOT_LABEL, version)?;
+    }
+    kiprintln!("usage roots rebuilt from {} indexed notes", versions.len());
+    Ok(())
+}
+
+pub fn list_usage_roots(state: &ResellerState, request: ListUsageRoots) -> anyhow::Result<Vec<IndexedUsageRoot>> {
+    let namehash = match &request.reseller {
+        Some(reseller) => Some(
+            state
+                .db
+                .namehash_of(reseller)?
+                .ok_or_else(|| anyhow::anyhow!("Reseller not found: {reseller}"))?,
+        ),
+        None => None,
+    };
+    state.db.usage_roots(namehash.as_deref())
+}
+
+/// The proof of one of our receipts, against the root of its period as it stands.
+/// Only the receipt's customer may ask for it; `None` means our own node.
+pub fn usage_proof(state: &ResellerState, request_id: &str, customer: Option<&str>) -> anyhow::Result<UsageProof> {
+    let signed = state
+        .db
+        .receipt(request_id)?
+        .filter(|signed| signed.receipt.reseller == our().node)
+        .ok_or_else(|| anyhow::anyhow!("no receipt {request_id} issued by this node"))?;
+    if customer.is_some_and(|customer| customer != signed.receipt.customer) {
+        return Err(anyhow::anyhow!("receipt {request_id} belongs to another customer"));
+    }
+    // a committed period keeps the length it was committed with, whatever the config says now
+    let timestamp = signed.receipt.timestamp;
+    let (period, length) = match state
+        .usage_commitments
+        .values()
+        .find(|commitment| (commitment.note.from..commitment.note.to).contains(&timestamp))
+    {
+        Some(commitment) => (commitment.note.period, commitment.note.length()),
+        None => (period_of(state, timestamp)?, state.config.usage_period),
+    };
+    let (note, receipts) = period_receipts(state, period, length)?;
+    let leaves = receipts.iter().map(leaf).collect::<anyhow::Result<Vec<B256>>>()?;
+    let index = receipts
+        .iter()
+        .position(|receipt| receipt.receipt.request_id == request_id)
+        .ok_or_else(|| anyhow::anyhow!("receipt {request_id} is missing from period {period}"))?;
+    Ok(UsageProof {
+        leaf: leaves[index],
+        proof: merkle_proof(&leaves, index),
+        root: note.root,
+        receipt: signed,
+        period,
+        published_root: None,
+        verified: false,
+    })
+}
+
+/// Fetch a receipt's proof from the reseller that issued it, then check it against
+/// the roots that reseller's entries published.
+pub fn get_usage_proof(state: &mut ResellerState, request: GetUsageProof) -> anyhow::Result<UsageProof> {
+    let node = request.reseller.unwrap_or_else(|| our().node.clone());
+    let mut proof = if node == our().node {
+        usage_proof(state, &request.request_id, None)?
+    } else {
+        let body = serde_json::to_vec(&ResellerRequest::GetUsageProof(request.request_id.clone()))?;
+        let response = Request::to(ProcessAddress::new(&node, our().process.clone()))
+            .body(body)
+            .send_and_await_response(PROOF_REQUEST_TIMEOUT)
+            .map_err(|e| anyhow::anyhow!("send failed: {e:?}"))?
+            .map_err(|e| anyhow::anyhow!("send failed: {e:?}"))?;
+        serde_json::from_slice::<Result<UsageProof, String>>(response.body())?.map_err(|e| anyhow::anyhow!(e))?
+    };
+    if proof.receipt.receipt.reseller != node || leaf(&proof.receipt)? != proof.leaf {
+        return Err(anyhow::anyhow!("{node} returned a proof for a different receipt"));
+    }
+
+    // the node's reseller entries, from the registry's node names or our own offering
+    let mut namehashes: Vec<String> = state
+        .db
+        .list_resellers()?
+        .into_iter()
+        .filter(|entry| entry.node_name.as_deref() == Some(node.as_str()))
+        .filter_map(|entry| entry.namehash)
+        .collect();
+    if node == our().node {
+        if let Some(offering) = &state.offering {
+            namehashes.extend(state.db.namehash_of(&offering.reseller)?);
+        }
+    }
+    proof.published_root = None;
+    for namehash in namehashes {
+        if let Some(root) = state.db.usage_root(&namehash, proof.period)? {
+            proof.published_root = Some(root.note.root);
+            break;
+        }
+    }
+    proof.verified = proof.published_root.is_some_and(|root| verify_proof(proof.leaf, &proof.proof, root));
+    Ok(proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<B256> {
+        (0..count).map(|i| keccak256([i])).collect()
+    }
+
+    #[test]
+    fn root_of_no_leaves_is_zero() {
+        assert_eq!(merkle_root(&[]), B256::ZERO);
+    }
+
+    #[test]
+    fn root_of_one_leaf_is_the_leaf() {
+        let leaves = leaves(1);
+        assert_eq!(merkle_root(&leaves), leaves[0]);
+        assert!(merkle_proof(&leaves, 0).is_empty());
+    }
+
+    #[test]
+    fn root_of_two_leaves_is_order_independent() {
+        let leaves = leaves(2);
+        let reversed = [leaves[1], leaves[0]];
+        assert_eq!(merkle_root(&leaves), merkle_root(&reversed));
+        assert_eq!(merkle_root(&leaves), hash_pair(leaves[0], leaves[1]));
+    }
+
+    #[test]
+    fn every_leaf_proves_against_the_root() {
+        // odd sizes leave nodes without a sibling at some level
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = merkle_root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, index);
+                assert!(verify_proof(*leaf, &proof, root), "leaf {index} of {count}");
+            }
+        }
+    }
+
+    #[test]
+    fn proof_fails_for_another_leaf_or_root() {
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 2);
+        assert!(!verify_proof(keccak256([0xff]), &proof, root));
+        assert!(!verify_proof(leaves[2], &proof, merkle_root(&leaves[..4])));
+    }
+}
@@ -0,0 +1,362 @@
+use std::borrow::Cow;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use alloy_primitives::{fixed_bytes, Address, Bytes, FixedBytes, PrimitiveSignature, B256, U256};
+use alloy_signer::SignerSync;
+use alloy_sol_types::{sol, Eip712Domain, SolStruct};
+use kinode_process_lib::kiprintln;
+
+use crate::contracts::{
+    eth_call,
+    ERC1271
+};
+use crate::payments::{
+    quote,
+    reseller_tba
+};
+use crate::structs::ResellerState;
+
+/// EIP-712 domain name vouchers are signed under.
+const VOUCHER_DOMAIN_NAME: &str = "reseller-test";
+const VOUCHER_DOMAIN_VERSION: &str = "1";
+/// What `isValidSignature` returns for a signature the contract accepts.
+const ERC1271_MAGIC_VALUE: FixedBytes<4> = fixed_bytes!("1626ba7e");
+
+sol! {
+    /// The EIP-712 message a buyer signs: `payer` owes `payee` a running total of
+    /// `cumulativeAmount` base units of `token`.
+    struct Voucher {
+        address payer;
+        address payee;
+        address token;
+        uint256 cumulativeAmount;
+    }
+}
+
+/// A signed IOU sent along with a call in place of an on-chain payment. Each voucher
+/// replaces the last, so only the latest one needs settling.
+///
+/// # Example
+/// ```
+/// let voucher = SignedVoucher {
+///     payer: hot_wallet,
+///     payee: reseller_tba,
+///     token: usdc,
+///     cumulative_amount: U256::from(30_000),
+///     signature: Bytes::from(signature.as_bytes().to_vec()),
+/// };
+/// ```
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct SignedVoucher {
+    /// an EOA, or a contract (e.g. the buyer's tba) implementing ERC-1271
+    pub payer: Address,
+    /// the reseller's tba
+    pub payee: Address,
+    pub token: Address,
+    /// everything the payer owes the payee so far, in base units
+    pub cumulative_amount: U256,
+    /// 65-byte ECDSA signature, or whatever the payer contract's `isValidSignature` accepts
+    pub signature: Bytes,
+}
+
+/// The latest voucher a payer gave us, and what we've charged against it.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct StoredVoucher {
+    #[serde(flatten)]
+    pub voucher: SignedVoucher,
+    /// sum of the prices of the calls we served this payer, in base units
+    pub charged: U256,
+    pub calls: u64,
+}
+
+impl SignedVoucher {
+    /// The EIP-712 hash the signature is over, in a domain bound to the payee.
+    pub fn signing_hash(&self, chain_id: u64) -> B256 {
+        Voucher {
+            payer: self.payer,
+            payee: self.payee,
+            token: self.token,
+            cumulativeAmount: self.cumulative_amount,
+        }
+        .eip712_signing_hash(&domain(chain_id, self.payee))
+    }
+}
+
+/// The payee's tba is the contract a voucher is redeemed against, so it is the
+/// domain's `verifyingContract`.
+fn domain(chain_id: u64, payee: Address) -> Eip712Domain {
+    Eip712Domain::new(
+        Some(Cow::Borrowed(VOUCHER_DOMAIN_NAME)),
+        Some(Cow::Borrowed(VOUCHER_DOMAIN_VERSION)),
+        Some(U256::from(chain_id)),
+        Some(payee),
+        None,
+    )
+}
+
+/// Sign, with the hot wallet, a voucher raising what we owe `payee` by `price`. The
+/// raised total is only kept once `commit_voucher` is called for the served call.
+pub fn sign_voucher(state: &mut ResellerState, payee: Address, price: U256) -> anyhow::Result<SignedVoucher> {
+    let token = state
+        .config
+        .payment_token
+        .ok_or_else(|| anyhow::anyhow!("no payment_token in the package config"))?;
+    let signer = state
+        .wallet
+        .as_ref()
+        .and_then(|wallet| wallet.signer.as_ref())
+        .ok_or_else(|| anyhow::anyhow!("hot wallet is missing or locked; vouchers are signed with it"))?;
+
+    let committed = state.voucher_totals.get(&payee).copied().unwrap_or_default();
+    let mut voucher = SignedVoucher {
+        payer: signer.address(),
+        payee,
+        token,
+        cumulative_amount: committed
+            .checked_add(price)
+            .ok_or_else(|| anyhow::anyhow!("voucher total for {payee} overflows"))?,
+        signature: Bytes::new(),
+    };
+    let signature = signer.sign_hash_sync(&voucher.signing_hash(state.config.chain_id))?;
+    voucher.signature = Bytes::from(signature.as_bytes().to_vec());
+    Ok(voucher)
+}
+
+/// Record a voucher's total once the reseller it was sent to served the call; the
+/// reseller only keeps vouchers for calls it served.
+pub fn commit_voucher(state: &mut ResellerState, voucher: &SignedVoucher) {
+    state.voucher_totals.insert(voucher.payee, voucher.cumulative_amount);
+    state.save();
+}
+
+/// Catch up with the latest voucher a payee holds from us, after a call whose outcome
+/// we didn't learn: the payee may have served and charged it. Only a voucher we signed
+/// ourselves can raise our total.
+pub fn sync_voucher_total(state: &mut ResellerState, payee: Address, stored: Option<StoredVoucher>) -> anyhow::Result<()> {
+    let payer = state
+        .wallet
+        .as_ref()
+        .map(|wallet| wallet.address)
+        .ok_or_else(|| anyhow::anyhow!("no hot wallet; vouchers are signed with it"))?;
+    let committed = state.voucher_totals.get(&payee).copied().unwrap_or_default();
+    let total = match &stored {
+        Some(stored) => caught_up_total(committed, stored, payer, payee, state.config.chain_id)?,
+        None => committed,
+    };
+    if total != committed {
+        kiprintln!("voucher total for {payee} caught up from {committed} to {total}");
+        state.voucher_totals.insert(payee, total);
+        state.save();
+    }
+    Ok(())
+}
+
+/// The higher of our committed total and the total of a voucher the payee holds, if
+/// that voucher is really ours.
+fn caught_up_total(
+    committed: U256,
+    stored: &StoredVoucher,
+    payer: Address,
+    payee: Address,
+    chain_id: u64,
+) -> anyhow::Result<U256> {
+    let voucher = &stored.voucher;
+    if voucher.payer != payer || voucher.payee != payee {
+        return Err(anyhow::anyhow!("voucher from {} to {} is not ours", voucher.payer, voucher.payee));
+    }
+    let recovered = PrimitiveSignature::try_from(voucher.signature.as_ref())
+        .ok()
+        .and_then(|signature| signature.recover_address_from_prehash(&voucher.signing_hash(chain_id)).ok());
+    if recovered != Some(payer) {
+        return Err(anyhow::anyhow!("voucher held by {payee} is not signed by {payer}"));
+    }
+    Ok(committed.max(voucher.cumulative_amount))
+}
+
+/// Check a buyer's voucher before serving a call: it must be signed by the payer, be
+/// made out to our tba in the payment token, never lower the payer's total, and cover
+/// every call charged so far plus this one. Returns the voucher with the call charged,
+/// for `accept_voucher` to keep once the call is served.
+pub fn check_voucher(state: &mut ResellerState, voucher: SignedVoucher, model: &str) -> anyhow::Result<StoredVoucher> {
+    let tba = reseller_tba(state)?;
+    if voucher.payee != tba {
+        return Err(anyhow::anyhow!("voucher is made out to {}, not our tba {tba}", voucher.payee));
+    }
+    if Some(voucher.token) != state.config.payment_token {
+        return Err(anyhow::anyhow!("voucher is in {}, which we don't accept", voucher.token));
+    }
+    let price = quote(state, model)?;
+
+    let previous = state.db.voucher(&voucher.payer)?;
+    let (charged, calls) = match &previous {
+        Some(previous) => {
+            if voucher.cumulative_amount < previous.voucher.cumulative_amount {
+                return Err(anyhow::anyhow!(
+                    "voucher total {} is below the {} {} already signed",
+                    voucher.cumulative_amount,
+                    previous.voucher.cumulative_amount,
+                    voucher.payer
+                ));
+            }
+            (previous.charged, previous.calls)
+        }
+        None => (U256::ZERO, 0),
+    };
+    let owed = charged.saturating_add(price);
+    if voucher.cumulative_amount < owed {
+        return Err(anyhow::anyhow!(
+            "voucher covers {} but {} is owed including this call",
+            voucher.cumulative_amount,
+            owed
+        ));
+    }
+
+    verify_signature(state, &voucher)?;
+
+    Ok(StoredVoucher {
+        voucher,
+        charged: owed,
+        calls: calls + 1,
+    })
+}
+
+/// Keep a checked voucher, charging the call it paid for.
+pub fn accept_voucher(state: &mut ResellerState, stored: &StoredVoucher) -> anyhow::Result<()> {
+    state.db.upsert_voucher(stored)?;
+    kiprintln!(
+        "accepted voucher from {}: {} of {} base units charged over {} calls",
+        stored.voucher.payer,
+        stored.charged,
+        stored.voucher.cumulative_amount,
+        stored.calls
+    );
+    Ok(())
+}
+
+/// An EOA payer must have signed the voucher itself; any other payer is asked
+/// through ERC-1271 whether it accepts the signature.
+fn verify_signature(state: &ResellerState, voucher: &SignedVoucher) -> anyhow::Result<()> {
+    let hash = voucher.signing_hash(state.config.chain_id);
+    let recovered = PrimitiveSignature::try_from(voucher.signature.as_ref())
+        .ok()
+        .and_then(|signature| signature.recover_address_from_prehash(&hash).ok());
+    if recovered == Some(voucher.payer) {
+        return Ok(());
+    }
+
+    let call = ERC1271::isValidSignatureCall {
+        hash,
+        signature: voucher.signature.clone(),
+    };
+    match eth_call(&state.kimap.provider, voucher.payer, call) {
+        Ok(result) if result.magicValue == ERC1271_MAGIC_VALUE => Ok(()),
+        Ok(_) => Err(anyhow::anyhow!("{} rejected the voucher signature", voucher.payer)),
+        Err(e) => Err(anyhow::anyhow!(
+            "voucher is not signed by {} (recovered {recovered:?}), and it can't vouch for it: {e}",
+            voucher.payer
+        )),
+    }
+}
+
+/// The latest voucher from every payer, for settlement.
+pub fn list_vouchers(state: &ResellerState) -> anyhow::Result<Vec<StoredVoucher>> {
+    state.db.vouchers()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::address;
+    use alloy_signer_local::PrivateKeySigner;
+
+    const CHAIN_ID: u64 = 8453;
+
+    fn signed(signer: &PrivateKeySigner, payee: Address, cumulative_amount: u64) -> SignedVoucher {
+        let mut voucher = SignedVoucher {
+            payer: signer.address(),
+            payee,
+            token: address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913"),
+            cumulative_amount: U256::from(cumulative_amount),
+            signature: Bytes::new(),
+        };
+        let signature = signer.sign_hash_sync(&voucher.signing_hash(CHAIN_ID)).unwrap();
+        voucher.signature = Bytes::from(signature.as_bytes().to_vec());
+        voucher
+    }
+
+    fn recover(voucher: &SignedVoucher, chain_id: u64) -> Address {
+        PrimitiveSignature::try_from(voucher.signature.as_ref())
+            .unwrap()
+            .recover_address_from_prehash(&voucher.signing_hash(chain_id))
+            .unwrap()
+    }
+
+    #[test]
+    fn signature_recovers_the_payer() {
+        let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+        let voucher = signed(&signer, Address::repeat_byte(0xaa), 30_000);
+        assert_eq!(recover(&voucher, CHAIN_ID), signer.address());
+    }
+
+    #[test]
+    fn changed_voucher_does_not_recover_the_payer() {
+        let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+        let mut voucher = signed(&signer, Address::repeat_byte(0xaa), 30_000);
+        assert_ne!(recover(&voucher, CHAIN_ID + 1), signer.address());
+        voucher.cumulative_amount = U256::from(60_000);
+        assert_ne!(recover(&voucher, CHAIN_ID), signer.address());
+    }
+
+    #[test]
+    fn total_catches_up_with_a_voucher_the_payee_charged() {
+        let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+        let payee = Address::repeat_byte(0xaa);
+        // we timed out on the call paid by this voucher, but the payee served it
+        let stored = StoredVoucher {
+            voucher: signed(&signer, payee, 60_000),
+            charged: U256::from(60_000),
+            calls: 2,
+        };
+        let caught_up = caught_up_total(U256::from(30_000), &stored, signer.address(), payee, CHAIN_ID).unwrap();
+        assert_eq!(caught_up, U256::from(60_000));
+        // a stale voucher never lowers our total
+        let ahead = caught_up_total(U256::from(90_000), &stored, signer.address(), payee, CHAIN_ID).unwrap();
+        assert_eq!(ahead, U256::from(90_000));
+    }
+
+    #[test]
+    fn total_only_catches_up_with_our_own_vouchers() {
+        let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+        let other = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x22)).unwrap();
+        let payee = Address::repeat_byte(0xaa);
+        let mut forged = StoredVoucher {
+            voucher: signed(&other, payee, 1_000_000),
+            charged: U256::from(1_000_000),
+            calls: 1,
+        };
+        forged.voucher.payer = signer.address();
+        assert!(caught_up_total(U256::ZERO, &forged, signer.address(), payee, CHAIN_ID).is_err());
+
+        let elsewhere = StoredVoucher {
+            voucher: signed(&signer, Address::repeat_byte(0xbb), 60_000),
+            charged: U256::from(60_000),
+            calls: 1,
+        };
+        assert!(caught_up_total(U256::ZERO, &elsewhere, signer.address(), payee, CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn domain_is_bound_to_the_payee() {
+        let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+        let voucher = signed(&signer, Address::repeat_byte(0xaa), 30_000);
+        let redirected = SignedVoucher {
+            payee: Address::repeat_byte(0xbb),
+            ..voucher.clone()
+        };
+        assert_ne!(voucher.signing_hash(CHAIN_ID), redirected.signing_hash(CHAIN_ID));
+        assert_eq!(domain(CHAIN_ID, voucher.payee).verifying_contract, Some(voucher.payee));
+    }
+}
@@ -0,0 +1,379 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use process_macros::SerdeJsonInto;
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm,
+    Nonce
+};
+use alloy_consensus::{SignableTransaction, TxEip1559, TxEnvelope};
+use alloy_eips::eip2718::Encodable2718;
+use alloy_network::TxSignerSync;
+use alloy_primitives::{hex, Address, Bytes, TxKind, B256, U256};
+use alloy_signer_local::PrivateKeySigner;
+use rand::RngCore;
+use kinode_process_lib::{
+    kiprintln,
+    eth
+};
+
+use crate::contracts::UnsignedTransaction;
+use crate::structs::ResellerState;
+
+/// PBKDF2 rounds for deriving the key-encryption key from the password.
+const KDF_ROUNDS: u32 = 210_000;
+/// Transactions kept in the wallet's history; the oldest are dropped first.
+const MAX_TRACKED_TRANSACTIONS: usize = 100;
+/// Added to the gas estimate so small state changes between estimate and inclusion don't revert.
+const GAS_LIMIT_MARGIN_PERCENT: u64 = 20;
+/// Printed in place of keys and passwords.
+const REDACTED: &str = "<redacted>";
+
+/// Create the process's hot wallet, importing a key or generating a new one.
+///
+/// # Example
+/// ```
+/// let request = CreateWallet {
+///     private_key: None,
+///     password: "correct horse battery staple".to_string(),
+///     replace: false,
+///     current_password: None,
+/// };
+/// ```
+#[derive(Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct CreateWallet {
+    /// 0x-prefixed hex; a new key is generated if this is `None`
+    pub private_key: Option<String>,
+    /// encrypts the key in state; needed again to unlock after every restart
+    pub password: String,
+    /// must be set to replace an existing wallet
+    #[serde(default)]
+    pub replace: bool,
+    /// the existing wallet's password, when replacing it
+    #[serde(default)]
+    pub current_password: Option<String>,
+}
+
+// requests are logged; keep the key and passwords out of the terminal
+impl fmt::Debug for CreateWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreateWallet")
+            .field("private_key", &self.private_key.as_ref().map(|_| REDACTED))
+            .field("password", &REDACTED)
+            .field("replace", &self.replace)
+            .field("current_password", &self.current_password.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+/// Decrypt the hot wallet's key into memory, until the process restarts or `LockWallet`.
+/// Sent as a bare string, the password.
+///
+/// # Example
+/// ```
+/// let request = UnlockWallet {
+///     password: "correct horse battery staple".to_string(),
+/// };
+/// ```
+#[derive(Deserialize, Serialize, SerdeJsonInto, Clone)]
+#[serde(transparent)]
+pub struct UnlockWallet {
+    pub password: String,
+}
+
+impl fmt::Debug for UnlockWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnlockWallet").field("password", &REDACTED).finish()
+    }
+}
+
+/// A signing key encrypted with AES-256-GCM under a PBKDF2-SHA256 key.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EncryptedKey {
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// A process-managed signing key, meant to be an operator of our reseller tba.
+///
+/// Only the encrypted key is persisted. The decrypted signer lives in memory from
+/// `UnlockWallet` until `LockWallet` or the next restart.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HotWallet {
+    pub address: Address,
+    pub encrypted_key: EncryptedKey,
+    /// nonce of the next transaction; `None` means ask the chain
+    pub next_nonce: Option<u64>,
+    /// sent transactions, oldest first
+    pub transactions: Vec<TrackedTransaction>,
+    #[serde(skip)]
+    pub signer: Option<PrivateKeySigner>,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone, PartialEq)]
+pub enum TransactionStatus {
+    Pending,
+    /// mined; `success` is false if it reverted
+    Mined { block: Option<u64>, success: bool },
+}
+
+/// A transaction the hot wallet sent.
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct TrackedTransaction {
+    pub hash: B256,
+    pub nonce: u64,
+    pub to: Address,
+    pub description: String,
+    pub status: TransactionStatus,
+}
+
+#[derive(Debug, Deserialize, Serialize, SerdeJsonInto, Clone)]
+pub struct WalletStatus {
+    /// `None` until `CreateWallet`
+    pub address: Option<Address>,
+    pub unlocked: bool,
+    /// native balance in wei
+    pub balance: Option<U256>,
+    pub transactions: Vec<TrackedTransaction>,
+}
+
+impl EncryptedKey {
+    fn encrypt(secret: &[u8], password: &str) -> anyhow::Result<Self> {
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher(password, &salt)?
+            .encrypt(Nonce::from_slice(&nonce), secret)
+            .map_err(|_| anyhow::anyhow!("failed to encrypt signing key"))?;
+        Ok(EncryptedKey {
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    fn decrypt(&self, password: &str) -> anyhow::Result<Vec<u8>> {
+        let salt = hex::decode(&self.salt)?;
+        let nonce = hex::decode(&self.nonce)?;
+        cipher(password, &salt)?
+            .decrypt(Nonce::from_slice(&nonce), hex::decode(&self.ciphertext)?.as_slice())
+            .map_err(|_| anyhow::anyhow!("wrong password"))
+    }
+}
+
+fn cipher(password: &str, salt: &[u8]) -> anyhow::Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password.as_bytes(), salt, KDF_ROUNDS, &mut key);
+    Aes256Gcm::new_from_slice(&key).map_err(|e| anyhow::anyhow!("bad key length: {e}"))
+}
+
+/// Create the hot wallet, or replace it given `replace` and the current password. The
+/// new wallet starts unlocked.
+pub fn create_wallet(state: &mut ResellerState, request: CreateWallet) -> anyhow::Result<WalletStatus> {
+    if let Some(wallet) = state.wallet.as_ref() {
+        if !request.replace {
+            return Err(anyhow::anyhow!(
+                "hot wallet {} already exists; set `replace` and `current_password` to replace it",
+                wallet.address
+            ));
+        }
+        let current_password = request
+            .current_password
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("`current_password` is required to replace {}", wallet.address))?;
+        wallet.encrypted_key.decrypt(current_password)?;
+        kiprintln!("replacing hot wallet {}", wallet.address);
+    }
+    let signer = match request.private_key {
+        Some(key) => PrivateKeySigner::from_str(&key)?,
+        None => PrivateKeySigner::random(),
+    };
+    let encrypted_key = EncryptedKey::encrypt(&signer.to_bytes()[..], &request.password)?;
+    kiprintln!("hot wallet set to {}", signer.address());
+    state.wallet = Some(HotWallet {
+        address: signer.address(),
+        encrypted_key,
+        next_nonce: None,
+        transactions: vec![],
+        signer: Some(signer),
+    });
+    state.save();
+    wallet_status(state)
+}
+
+pub fn unlock_wallet(state: &mut ResellerState, request: UnlockWallet) -> anyhow::Result<WalletStatus> {
+    let wallet = state
+        .wallet
+        .as_mut()
+        .ok_or_else(|| anyhow::anyhow!("no hot wallet; send CreateWallet first"))?;
+    let secret = wallet.encrypted_key.decrypt(&request.password)?;
+    let signer = PrivateKeySigner::from_slice(&secret)?;
+    if signer.address() != wallet.address {
+        return Err(anyhow::anyhow!("decrypted key does not match {}", wallet.address));
+    }
+    wallet.signer = Some(signer);
+    wallet_status(state)
+}
+
+pub fn lock_wallet(state: &mut ResellerState) -> anyhow::Result<WalletStatus> {
+    if let Some(wallet) = state.wallet.as_mut() {
+        wallet.signer = None;
+    }
+    wallet_status(state)
+}
+
+/// The wallet's address, balance and transactions, after checking pending receipts.
+pub fn wallet_status(state: &mut ResellerState) -> anyhow::Result<WalletStatus> {
+    poll_receipts(state);
+    let Some(wallet) = state.wallet.as_ref() else {
+        return Ok(WalletStatus {
+            address: None,
+            unlocked: false,
+            balance: None,
+            transactions: vec![],
+        });
+    };
+    let balance = state.kimap.provider.get_balance(wallet.address, None).ok();
+    Ok(WalletStatus {
+        address: Some(wallet.address),
+        unlocked: wallet.signer.is_some(),
+        balance,
+        transactions: wallet.transactions.clone(),
+    })
+}
+
+/// Sign a transaction as an EIP-1559 transaction from the hot wallet and send it
+/// through `eth:distro:sys`.
+pub fn send_transaction(state: &mut ResellerState, request: UnsignedTransaction) -> anyhow::Result<TrackedTransaction> {
+    if request.chain_id != state.config.chain_id {
+        return Err(anyhow::anyhow!(
+            "transaction is for chain {}, but this node is configured for chain {}",
+            request.chain_id,
+            state.config.chain_id
+        ));
+    }
+    let provider = state.kimap.provider.clone();
+    let wallet = state
+        .wallet
+        .as_mut()
+        .ok_or_else(|| anyhow::anyhow!("no hot wallet; send CreateWallet first"))?;
+    let signer = wallet
+        .signer
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("hot wallet is locked; send UnlockWallet first"))?;
+
+    let input = Bytes::from(hex::decode(&request.data)?);
+    let nonce = match wallet.next_nonce {
+        Some(nonce) => nonce,
+        None => provider
+            .get_transaction_count(wallet.address, Some(eth::BlockId::Number(eth::BlockNumberOrTag::Pending)))
+            .map_err(|e| anyhow::anyhow!("failed to fetch nonce: {e:?}"))?
+            .to::<u64>(),
+    };
+
+    let estimate = provider
+        .estimate_gas(
+            eth::TransactionRequest::default()
+                .from(wallet.address)
+                .to(request.to)
+                .value(request.value)
+                .input(eth::TransactionInput::new(input.clone())),
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("gas estimation failed (is the wallet an operator of the tba?): {e:?}"))?
+        .to::<u64>();
+    let (max_fee_per_gas, max_priority_fee_per_gas) = fees(&provider)?;
+
+    let mut tx = TxEip1559 {
+        chain_id: request.chain_id,
+        nonce,
+        gas_limit: estimate + estimate * GAS_LIMIT_MARGIN_PERCENT / 100,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        to: TxKind::Call(request.to),
+        value: request.value,
+        access_list: Default::default(),
+        input,
+    };
+    let signature = signer.sign_transaction_sync(&mut tx)?;
+    let raw = TxEnvelope::from(tx.into_signed(signature)).encoded_2718();
+
+    let hash = match provider.send_raw_transaction(raw.into()) {
+        Ok(hash) => hash,
+        Err(e) => {
+            // the nonce may be stale; refetch it for the next attempt
+            wallet.next_nonce = None;
+            state.save();
+            return Err(anyhow::anyhow!("failed to send transaction: {e:?}"));
+        }
+    };
+
+    let tracked = TrackedTransaction {
+        hash,
+        nonce,
+        to: request.to,
+        description: request.description,
+        status: TransactionStatus::Pending,
+    };
+    kiprintln!("sent {} as {hash} (nonce {nonce})", tracked.description);
+    wallet.next_nonce = Some(nonce + 1);
+    wallet.transactions.push(tracked.clone());
+    if wallet.transactions.len() > MAX_TRACKED_TRANSACTIONS {
+        wallet.transactions.remove(0);
+    }
+    state.save();
+    Ok(tracked)
+}
+
+/// `(max_fee_per_gas, max_priority_fee_per_gas)`: the tip the node's gas price implies
+/// on top of the latest base fee, with room for the base fee to double.
+fn fees(provider: &eth::Provider) -> anyhow::Result<(u128, u128)> {
+    let gas_price = provider
+        .get_gas_price()
+        .map_err(|e| anyhow::anyhow!("failed to fetch gas price: {e:?}"))?
+        .to::<u128>();
+    let base_fee = provider
+        .get_block_by_number(eth::BlockNumberOrTag::Latest, false)
+        .map_err(|e| anyhow::anyhow!("failed to fetch latest block: {e:?}"))?
+        .and_then(|block| block.header.base_fee_per_gas)
+        .map(u128::from)
+        .unwrap_or(gas_price);
+    let priority_fee = gas_price.saturating_sub(base_fee).max(1);
+    Ok((base_fee * 2 + priority_fee, priority_fee))
+}
+
+/// Look up receipts for pending transactions. Called on the heartbeat and status requests.
+pub fn poll_receipts(state: &mut ResellerState) {
+    let provider = state.kimap.provider.clone();
+    let Some(wallet) = state.wallet.as_mut() else {
+        return;
+    };
+    let mut changed = false;
+    for tx in wallet
+        .transactions
+        .iter_mut()
+        .filter(|tx| tx.status == TransactionStatus::Pending)
+    {
+        match provider.get_transaction_receipt(tx.hash) {
+            Ok(Some(receipt)) => {
+                tx.status = TransactionStatus::Mined {
+                    block: receipt.block_number,
+                    success: receipt.status(),
+                };
+                kiprintln!("{} ({}) mined: {:?}", tx.description, tx.hash, tx.status);
+                changed = true;
+            }
+            Ok(None) => {}
+            Err(e) => kiprintln!("failed to fetch receipt for {}: {e:?}", tx.hash),
+        }
+    }
+    if changed {
+        state.save();
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
+use kinode_process_lib::{
+    kiprintln,
+    LazyLoadBlob,
+    http::server::{
+        send_ws_push,
+        HttpServer,
+        WsMessageType
+    }
+};
+use crate::structs::{
+    ResellerState,
+    IndexChange,
+    ChangesSince,
+    GetChangesSince
+};
+
+/// Path clients open a websocket on to follow index changes.
+pub const WS_PATH: &str = "/updates";
+
+/// A message pushed to websocket clients.
+#[derive(Debug, Serialize, Deserialize)]
+enum WsUpdate {
+    /// changes applied since the last push
+    Changes(Vec<IndexChange>),
+    /// reply to a client's `GetChangesSince`, to catch up after (re)connecting
+    ChangesSince(ChangesSince),
+}
+
+/// Handles messages from websocket clients.
+///
+/// Every connected client receives new changes as they are applied; a client that
+/// sends `{"block": N}` additionally gets everything after block N, so it can catch
+/// up on what it missed while disconnected.
+pub fn ws_handler(
+    state: &mut ResellerState,
+    _server: &mut HttpServer,
+    channel_id: u32,
+    msg_type: WsMessageType,
+    blob: LazyLoadBlob,
+) {
+    if !matches!(msg_type, WsMessageType::Text | WsMessageType::Binary) {
+        return;
+    }
+    let request = match serde_json::from_slice::<GetChangesSince>(blob.bytes()) {
+        Ok(request) => request,
+        Err(e) => {
+            kiprintln!("Ignoring websocket message on channel {channel_id}: {e}");
+            return;
+        }
+    };
+    match state.changes_since(request.block) {
+        Ok(changes) => {
+            send_ws_push(channel_id, WsMessageType::Text, to_blob(&WsUpdate::ChangesSince(changes)));
+        }
+        Err(e) => kiprintln!("Failed to read changes for channel {channel_id}: {e:?}"),
+    }
+}
+
+/// Pushes changes applied since the last push to every websocket client.
+pub fn push_changes(state: &mut ResellerState, server: &HttpServer) {
+    if state.unpushed_changes.is_empty() {
+        return;
+    }
+    let update = WsUpdate::Changes(std::mem::take(&mut state.unpushed_changes));
+    server.ws_push_all_channels(WS_PATH, WsMessageType::Text, to_blob(&update));
+}
+
+fn to_blob(update: &WsUpdate) -> LazyLoadBlob {
+    LazyLoadBlob::new(
+        Some("application/json"),
+        serde_json::to_vec(update).unwrap(),
+    )
+}
@@ -37,7 +37,7 @@ function App() {
     setResponse("");
 
     try {
-      const result = await fetch(`${BASE_URL}/api`, {
+      const result = await fetch(`${BASE_URL}/admin-api`, {
         method: "POST",
         headers: {
           "Content-Type": "application/json",
@@ -69,7 +69,7 @@ function App() {
     setKeyResponse("");
 
     try {
-      const result = await fetch(`${BASE_URL}/api`, {
+      const result = await fetch(`${BASE_URL}/admin-api`, {
         method: "POST",
         headers: {
           "Content-Type": "application/json",
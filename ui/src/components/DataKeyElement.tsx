@@ -1,8 +1,16 @@
 import React, { useState } from 'react';
 
+export interface DataVersion {
+    data: string,
+    block: number | null,
+    tx_hash: string | null,
+    log_index: number | null,
+    timestamp: number | null
+}
+
 export interface DataKey {
-    Note?: string[],
-    Fact?: string
+    Note?: DataVersion[],
+    Fact?: DataVersion
 }
 
 interface DataKeyElementProps {
@@ -96,7 +104,7 @@ export const DataKeyElement: React.FC<DataKeyElementProps> = ({ dataKey, dataVal
                                 Note History (newest to oldest):
                                 <button 
                                     className={`copy-button ${copySuccess ? 'success' : ''}`}
-                                    onClick={() => handleCopy(dataValue.Note!.join('\n'))}
+                                    onClick={() => handleCopy(dataValue.Note!.map(note => note.data).join('\n'))}
                                 >
                                     {copySuccess ? '✓' : '📋'}
                                 </button>
@@ -104,7 +112,7 @@ export const DataKeyElement: React.FC<DataKeyElementProps> = ({ dataKey, dataVal
                             {[...dataValue.Note].reverse().map((note, index) => (
                                 <div key={index} className="note-revision">
                                     <span className="revision-number">V.{dataValue.Note!.length - index}:</span>
-                                    <div dangerouslySetInnerHTML={{ __html: tryParseUtf8(note) }} />
+                                    <div dangerouslySetInnerHTML={{ __html: tryParseUtf8(note.data) }} />
                                 </div>
                             ))}
                         </div>
@@ -113,11 +121,11 @@ export const DataKeyElement: React.FC<DataKeyElementProps> = ({ dataKey, dataVal
                         <div>
                             <button 
                                 className={`copy-button ${copySuccess ? 'success' : ''}`}
-                                onClick={() => handleCopy(dataValue.Fact!)}
+                                onClick={() => handleCopy(dataValue.Fact!.data)}
                             >
                                 {copySuccess ? '✓' : '📋'}
                             </button>
-                            <div dangerouslySetInnerHTML={{ __html: tryParseUtf8(dataValue.Fact) }} />
+                            <div dangerouslySetInnerHTML={{ __html: tryParseUtf8(dataValue.Fact.data) }} />
                         </div>
                     )}
                 </div>
@@ -17,6 +17,7 @@ export interface Node {
   parent_path: string;
   child_names: string[];
   data_keys: Record<string, DataKey>;
+  decoded?: Record<string, { type: string; value: unknown }>;
 }
 
 export interface Info {
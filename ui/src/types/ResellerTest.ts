@@ -12,8 +12,17 @@ export interface ApiKeyUpdate {
     key: string;
   }
 
+export interface SyncStatus {
+  current_block: number;
+  head_block: number;
+  percentage: number;
+  live: boolean;
+  last_error: string | null;
+}
+
 export type HttpResponse = {
   Json?: ResellerApiResponse;
   Node?: Node;
+  SyncStatus?: SyncStatus;
   Text?: string;
 }
\ No newline at end of file